@@ -0,0 +1,173 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+
+use crate::merge::{merge_range, MergeConfig, MergeReport, TranscodeProfile, VideoCodec};
+use crate::store::{FsStore, SegmentStore};
+
+/// Default template for the clip file name
+pub const DEFAULT_CLIP_NAME: &str = "{name}-clip.{ext}";
+
+/// Profile used to cut clips at exact frames
+const PRECISE_PROFILE: TranscodeProfile = TranscodeProfile {
+    codec: VideoCodec::H264,
+    crf: 18,
+    video_bitrate: None,
+    audio_bitrate: 192,
+    max_size: None,
+    two_pass: false,
+};
+
+/// Point in a live stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipTime {
+    /// Seconds from the start of the broadcast, or from the first downloaded segment if the start
+    /// of the broadcast wasn't recorded.
+    Relative(f64),
+    /// Unix time in seconds.
+    WallClock(f64),
+}
+
+impl FromStr for ClipTime {
+    type Err = anyhow::Error;
+
+    /// Parse `[[HH:]MM:]SS[.fff]` as a relative time, or an RFC 3339 date and time or
+    /// `YYYY-MM-DD HH:MM:SS[.fff]` in local time as a wall-clock time.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(seconds) = parse_relative(s) {
+            return Ok(Self::Relative(seconds));
+        }
+
+        let datetime = DateTime::parse_from_rfc3339(s)
+            .map(|d| d.timestamp_millis())
+            .or_else(|_| {
+                ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+                    .iter()
+                    .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+                    .and_then(|d| Local.from_local_datetime(&d).single())
+                    .map(|d| d.timestamp_millis())
+                    .ok_or(())
+            })
+            .map_err(|_| anyhow!("Invalid time: {s}"))?;
+        Ok(Self::WallClock(datetime as f64 / 1000.0))
+    }
+}
+
+fn parse_relative(s: &str) -> Option<f64> {
+    let parts: Vec<_> = s.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let (seconds, rest) = parts.split_last()?;
+    let mut total: f64 = seconds.parse().ok().filter(|s: &f64| *s >= 0.0)?;
+    for (i, part) in rest.iter().rev().enumerate() {
+        total += part.parse::<u32>().ok()? as f64 * 60f64.powi(i as i32 + 1);
+    }
+    Some(total)
+}
+
+/// Options for clip
+#[derive(Clone, Debug, Default)]
+pub struct ClipConfig {
+    /// Start of the clip. If `None`, start at the beginning of the download.
+    pub from: Option<ClipTime>,
+
+    /// End of the clip. If `None`, end at the end of the download.
+    pub to: Option<ClipTime>,
+
+    /// Re-encode to cut at the exact frames, unless [MergeConfig::transcode] is already set.
+    /// Otherwise video is cut at the nearest packets, which may not start with a keyframe.
+    pub precise: bool,
+
+    /// Options for merging the selected segments.
+    /// If the output name is not set, [DEFAULT_CLIP_NAME] is used.
+    pub merge: MergeConfig,
+}
+
+/// Merge only the part of a live stream downloaded by [download][crate::download::download]
+/// between two times into a single video file.
+/// `ffmpeg` is required in `$PATH`.
+///
+/// # Arguments
+///
+/// `dir` - Directory containing downloaded video and audio segments.
+pub async fn clip(dir: impl AsRef<Path>, config: &ClipConfig) -> Result<MergeReport> {
+    clip_from(&FsStore::new(dir.as_ref()), dir, config).await
+}
+
+/// Merge only the part of the stream in `store` between two times into a single video file.
+/// See [clip].
+///
+/// # Arguments
+///
+/// `store` - Store containing downloaded video and audio segments.
+/// `dir` - Directory the stream was downloaded to, its name is used for the output file name.
+pub async fn clip_from(
+    store: &dyn SegmentStore,
+    dir: impl AsRef<Path>,
+    config: &ClipConfig,
+) -> Result<MergeReport> {
+    let meta = store.get_meta().await?;
+    let first_t = store
+        .list_segments()
+        .await?
+        .iter()
+        .map(|k| k.t)
+        .min()
+        .ok_or_else(|| anyhow!("No segments to clip"))?;
+
+    // Convert to segment times in milliseconds
+    let to_t = |time: ClipTime| -> Result<usize> {
+        let t = match (time, &meta) {
+            (ClipTime::Relative(s), Some(meta)) => meta.publish_frame_time as f64 + s * 1000.0,
+            (ClipTime::Relative(s), None) => first_t as f64 + s * 1000.0,
+            (ClipTime::WallClock(u), Some(meta)) => {
+                meta.publish_frame_time as f64 + (u - meta.broadcast_start) * 1000.0
+            }
+            (ClipTime::WallClock(_), None) => {
+                bail!("Wall-clock times need the stream information recorded while downloading")
+            }
+        };
+        Ok(t.max(0.0) as usize)
+    };
+    let from = config.from.map(to_t).transpose()?.unwrap_or(0);
+    let to = config.to.map(to_t).transpose()?.unwrap_or(usize::MAX);
+    if from >= to {
+        bail!("Clip start must be before its end");
+    }
+
+    let mut merge_config = config.merge.clone();
+    merge_config
+        .output_name
+        .get_or_insert_with(|| DEFAULT_CLIP_NAME.to_owned());
+    if config.precise {
+        merge_config.transcode.get_or_insert(PRECISE_PROFILE);
+    }
+    merge_range(store, dir, &merge_config, Some(from..to)).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        assert_eq!("90".parse::<ClipTime>().unwrap(), ClipTime::Relative(90.0));
+        assert_eq!(
+            "1:02:03.5".parse::<ClipTime>().unwrap(),
+            ClipTime::Relative(3723.5)
+        );
+        assert_eq!(
+            "2023-07-01T12:00:00Z".parse::<ClipTime>().unwrap(),
+            ClipTime::WallClock(1688212800.0)
+        );
+        assert!(matches!(
+            "2023-07-01 12:00:00".parse::<ClipTime>().unwrap(),
+            ClipTime::WallClock(_)
+        ));
+        assert!("1:2:3:4".parse::<ClipTime>().is_err());
+        assert!("-5".parse::<ClipTime>().is_err());
+    }
+}
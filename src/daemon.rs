@@ -0,0 +1,646 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+
+use crate::download::{
+    download_with, DownloadConfig, DownloadContext, DownloadEvent, DownloadSegments, MediaType,
+};
+use crate::fetch::{FetchConfig, Fetcher};
+use crate::merge::{merge, MergeConfig};
+use crate::store::{unix_time, FsStore, IndexEntry, SegmentSource, SegmentStore};
+
+/// Options for [serve]
+#[derive(Clone, Debug)]
+pub struct DaemonConfig {
+    /// Address to listen on.
+    pub addr: SocketAddr,
+
+    /// Directory to keep the job list and event logs in.
+    pub state_dir: PathBuf,
+
+    /// Directory to place each stream's directory in.
+    /// If `None`, use the current directory.
+    pub output: Option<PathBuf>,
+
+    /// Limits shared by all jobs.
+    pub fetch: FetchConfig,
+
+    /// Choose whether to download live segments or past segments.
+    pub segments: DownloadSegments,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+    Stopped,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct JobStats {
+    video_segments: usize,
+    audio_segments: usize,
+    past_segments: usize,
+    missed_live_segments: usize,
+    past_video_complete: bool,
+    past_audio_complete: bool,
+}
+
+impl JobStats {
+    fn add(&mut self, media_type: &MediaType, past: bool) {
+        match media_type {
+            MediaType::Video => self.video_segments += 1,
+            MediaType::Audio => self.audio_segments += 1,
+            MediaType::Unknown => {}
+        }
+        if past {
+            self.past_segments += 1;
+        }
+    }
+
+    fn count(&mut self, entry: &IndexEntry) {
+        self.add(&entry.media_type, entry.source == SegmentSource::Past);
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Job {
+    id: u64,
+    mpd_url: String,
+    status: JobStatus,
+    merging: bool,
+    stream_id: Option<String>,
+    dir: Option<PathBuf>,
+    error: Option<String>,
+    stats: JobStats,
+}
+
+#[derive(Deserialize)]
+struct SubmitRequest {
+    url: String,
+}
+
+struct Daemon {
+    config: DaemonConfig,
+    ctx: DownloadContext,
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    handles: Mutex<HashMap<u64, AbortHandle>>,
+    /// Segments counted in each job's statistics, so segments downloaded again after resuming
+    /// are not counted twice.
+    counted: Mutex<HashMap<u64, HashSet<(MediaType, usize)>>>,
+    next_id: AtomicU64,
+}
+
+/// Run a long-running download daemon controlled through a local HTTP/JSON API.
+///
+/// The job list is kept in `state_dir` and interrupted jobs are resumed on startup.
+///
+/// | Request                  | Action                                 |
+/// |--------------------------|----------------------------------------|
+/// | `POST /jobs`             | Submit `{"url": "<mpd url>"}`          |
+/// | `GET /jobs`              | List all jobs                          |
+/// | `GET /jobs/<id>`         | Get one job                            |
+/// | `POST /jobs/<id>/stop`   | Stop a running job                     |
+/// | `POST /jobs/<id>/merge`  | Merge a job's segments                 |
+/// | `GET /jobs/<id>/events`  | Get a job's event log                  |
+pub async fn serve(config: DaemonConfig) -> Result<()> {
+    let addr = config.addr;
+    let daemon = Daemon::open(config).await?;
+    daemon.resume().await;
+
+    // Serve API
+    let make_svc = make_service_fn(move |_| {
+        let daemon = daemon.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let daemon = daemon.clone();
+                async move { Ok::<_, Infallible>(daemon.handle(req).await) }
+            }))
+        }
+    });
+    println!("Listening on http://{addr}");
+    Server::bind(&addr).serve(make_svc).await?;
+
+    Ok(())
+}
+
+impl Daemon {
+    /// Restore the job list from the state directory
+    async fn open(config: DaemonConfig) -> Result<Arc<Self>> {
+        fs::create_dir_all(config.state_dir.join("events")).await?;
+
+        let jobs: BTreeMap<u64, Job> = match fs::read(config.state_dir.join("jobs.json")).await {
+            Ok(data) => serde_json::from_slice::<Vec<Job>>(&data)?
+                .into_iter()
+                .map(|j| (j.id, j))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let next_id = jobs.keys().next_back().map_or(1, |id| id + 1);
+
+        let ctx = DownloadContext {
+            fetcher: Fetcher::new(&config.fetch)?,
+            progress: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            stream_headers: false,
+        };
+        Ok(Arc::new(Self {
+            config,
+            ctx,
+            jobs: Mutex::new(jobs),
+            handles: Mutex::new(HashMap::new()),
+            counted: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(next_id),
+        }))
+    }
+
+    /// Restart jobs that were interrupted by the previous shutdown
+    async fn resume(self: &Arc<Self>) {
+        let interrupted: Vec<_> = self
+            .jobs
+            .lock()
+            .await
+            .values()
+            .filter(|j| j.status == JobStatus::Running || j.merging)
+            .map(|j| (j.id, j.status))
+            .collect();
+        for (id, status) in interrupted {
+            if status == JobStatus::Running {
+                self.log(id, json!({ "event": "resumed" })).await;
+                self.start_download(id).await;
+            } else {
+                self.start_merge(id).await;
+            }
+        }
+    }
+
+    async fn handle(self: Arc<Self>, req: Request<Body>) -> Response<Body> {
+        let path: Vec<_> = req
+            .uri()
+            .path()
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let path: Vec<_> = path.iter().map(String::as_str).collect();
+        let id = path.get(1).and_then(|id| id.parse::<u64>().ok());
+
+        let result = match (req.method(), path.as_slice(), id) {
+            (&Method::GET, ["jobs"], _) => {
+                let jobs: Vec<_> = self.jobs.lock().await.values().cloned().collect();
+                Ok((StatusCode::OK, json!(jobs)))
+            }
+            (&Method::POST, ["jobs"], _) => self.submit(req.into_body()).await,
+            (&Method::GET, ["jobs", _], Some(id)) => {
+                self.get(id).await.map(|j| (StatusCode::OK, j))
+            }
+            (&Method::POST, ["jobs", _, "stop"], Some(id)) => self.stop(id).await,
+            (&Method::POST, ["jobs", _, "merge"], Some(id)) => self.merge(id).await,
+            (&Method::GET, ["jobs", _, "events"], Some(id)) => self.events(id).await,
+            _ => Err((StatusCode::NOT_FOUND, "Not found".to_owned())),
+        };
+
+        let (status, body) = result.unwrap_or_else(|(s, e)| (s, json!({ "error": e })));
+        Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn submit(self: &Arc<Self>, body: Body) -> ApiResult {
+        let body = hyper::body::to_bytes(body)
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        let req: SubmitRequest =
+            serde_json::from_slice(&body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            id,
+            mpd_url: req.url,
+            status: JobStatus::Running,
+            merging: false,
+            stream_id: None,
+            dir: None,
+            error: None,
+            stats: JobStats::default(),
+        };
+        self.jobs.lock().await.insert(id, job.clone());
+        self.save().await;
+        self.log(id, json!({ "event": "submitted", "url": job.mpd_url }))
+            .await;
+        self.start_download(id).await;
+
+        Ok((StatusCode::CREATED, json!(job)))
+    }
+
+    async fn get(&self, id: u64) -> std::result::Result<Value, ApiError> {
+        self.jobs
+            .lock()
+            .await
+            .get(&id)
+            .map(|j| json!(j))
+            .ok_or((StatusCode::NOT_FOUND, format!("No job {id}")))
+    }
+
+    async fn stop(&self, id: u64) -> ApiResult {
+        self.get(id).await?;
+        match self.handles.lock().await.get(&id) {
+            Some(h) => h.abort(),
+            None => return Err((StatusCode::CONFLICT, format!("Job {id} is not running"))),
+        }
+        Ok((StatusCode::ACCEPTED, self.get(id).await?))
+    }
+
+    async fn merge(self: &Arc<Self>, id: u64) -> ApiResult {
+        {
+            let jobs = self.jobs.lock().await;
+            let job = jobs
+                .get(&id)
+                .ok_or((StatusCode::NOT_FOUND, format!("No job {id}")))?;
+            if job.status == JobStatus::Running || job.merging {
+                return Err((StatusCode::CONFLICT, format!("Job {id} is busy")));
+            }
+            if job.dir.is_none() {
+                return Err((StatusCode::CONFLICT, format!("Job {id} has no segments")));
+            }
+        }
+        self.start_merge(id).await;
+        Ok((StatusCode::ACCEPTED, self.get(id).await?))
+    }
+
+    async fn events(&self, id: u64) -> ApiResult {
+        self.get(id).await?;
+        let events: Vec<Value> = match fs::read_to_string(self.events_path(id)).await {
+            Ok(s) => s
+                .lines()
+                .filter_map(|l| serde_json::from_str(l).ok())
+                .collect(),
+            Err(_) => vec![],
+        };
+        Ok((StatusCode::OK, json!(events)))
+    }
+
+    async fn start_download(self: &Arc<Self>, id: u64) {
+        let (mpd_url, dir) = {
+            let jobs = self.jobs.lock().await;
+            (jobs[&id].mpd_url.clone(), jobs[&id].dir.clone())
+        };
+
+        // Count the segments already downloaded by a previous run, the download starts over with
+        // no knowledge of them and reports them again
+        let mut counted = HashSet::new();
+        if let Some(dir) = &dir {
+            let index = FsStore::new(dir).read_index().await.unwrap_or_default();
+            let mut jobs = self.jobs.lock().await;
+            let stats = &mut jobs.get_mut(&id).unwrap().stats;
+            stats.video_segments = 0;
+            stats.audio_segments = 0;
+            stats.past_segments = 0;
+            for entry in &index {
+                if counted.insert((entry.media_type.clone(), entry.t)) {
+                    stats.count(entry);
+                }
+            }
+        }
+        self.counted.lock().await.insert(id, counted);
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let config = DownloadConfig {
+            dir,
+            parent_dir: self.config.output.clone(),
+            segments: self.config.segments.clone(),
+            store: None,
+            events: Some(tx),
+            pipes: vec![],
+            incremental: false,
+            probe_log: None,
+            past_range: None,
+            limits: Default::default(),
+        };
+
+        let ctx = self.ctx.clone();
+        let handle = tokio::spawn(async move { download_with(&ctx, mpd_url, config).await });
+        self.handles.lock().await.insert(id, handle.abort_handle());
+
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            daemon.record_events(id, rx).await;
+            let (status, error) = match handle.await {
+                Ok(Ok(_)) => (JobStatus::Finished, None),
+                Ok(Err(e)) => (JobStatus::Failed, Some(e.to_string())),
+                Err(e) if e.is_cancelled() => (JobStatus::Stopped, None),
+                Err(e) => (JobStatus::Failed, Some(e.to_string())),
+            };
+            daemon.handles.lock().await.remove(&id);
+            daemon.counted.lock().await.remove(&id);
+            daemon
+                .update(id, |j| {
+                    j.status = status;
+                    j.error = error.clone();
+                })
+                .await;
+            daemon
+                .log(
+                    id,
+                    json!({ "event": "status", "status": status, "error": error }),
+                )
+                .await;
+        });
+    }
+
+    async fn start_merge(self: &Arc<Self>, id: u64) {
+        let Some(dir) = self.jobs.lock().await[&id].dir.clone() else {
+            return;
+        };
+        self.update(id, |j| j.merging = true).await;
+        self.log(id, json!({ "event": "merge_started" })).await;
+
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            let error = merge(&dir, &MergeConfig::default())
+                .await
+                .err()
+                .map(|e| e.to_string());
+            daemon.update(id, |j| j.merging = false).await;
+            daemon
+                .log(id, json!({ "event": "merge_finished", "error": error }))
+                .await;
+        });
+    }
+
+    async fn record_events(&self, id: u64, mut rx: UnboundedReceiver<DownloadEvent>) {
+        let mut unsaved = 0;
+        while let Some(event) = rx.recv().await {
+            let mut jobs = self.jobs.lock().await;
+            let job = jobs.get_mut(&id).unwrap();
+            match &event {
+                DownloadEvent::Started { stream_id, dir } => {
+                    job.stream_id = Some(stream_id.clone());
+                    job.dir = Some(dir.clone());
+                    unsaved = usize::MAX;
+                }
+                DownloadEvent::Segment {
+                    media_type,
+                    t,
+                    past,
+                } => {
+                    let new = self
+                        .counted
+                        .lock()
+                        .await
+                        .entry(id)
+                        .or_default()
+                        .insert((media_type.clone(), *t));
+                    if new {
+                        job.stats.add(media_type, *past);
+                        unsaved += 1;
+                    }
+                }
+                DownloadEvent::MissedSegment { .. } => {
+                    job.stats.missed_live_segments += 1;
+                    unsaved += 1;
+                }
+                DownloadEvent::PastFinished { media_type } => {
+                    match media_type {
+                        MediaType::Video => job.stats.past_video_complete = true,
+                        MediaType::Audio => job.stats.past_audio_complete = true,
+                        MediaType::Unknown => {}
+                    }
+                    unsaved = usize::MAX;
+                }
+                DownloadEvent::LimitReached { .. } | DownloadEvent::Finished => {}
+            }
+            drop(jobs);
+
+            // Don't rewrite the job list for every segment
+            if unsaved >= 50 {
+                self.save().await;
+                unsaved = 0;
+            }
+            self.log(id, json!(event)).await;
+        }
+    }
+
+    async fn update(&self, id: u64, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().await.get_mut(&id) {
+            f(job);
+        }
+        self.save().await;
+    }
+
+    async fn save(&self) {
+        // Keep the job list locked while writing so saves don't overtake each other
+        let jobs = self.jobs.lock().await;
+        let data = serde_json::to_vec_pretty(&jobs.values().collect::<Vec<_>>()).unwrap();
+        let path = self.config.state_dir.join("jobs.json");
+        let tmp = path.with_extension("json.tmp");
+        let result = match fs::write(&tmp, data).await {
+            Ok(()) => fs::rename(&tmp, &path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to save job list: {e}");
+        }
+    }
+
+    async fn log(&self, id: u64, mut event: Value) {
+        event["time"] = json!(unix_time());
+        let line = format!("{event}\n");
+        let result = match fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.events_path(id))
+            .await
+        {
+            Ok(mut f) => f.write_all(line.as_bytes()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write event log: {e}");
+        }
+    }
+
+    fn events_path(&self, id: u64) -> PathBuf {
+        self.config
+            .state_dir
+            .join("events")
+            .join(format!("{id}.jsonl"))
+    }
+}
+
+type ApiError = (StatusCode, String);
+type ApiResult = std::result::Result<(StatusCode, Value), ApiError>;
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    async fn request(daemon: &Arc<Daemon>, method: Method, path: &str, body: &str) -> Value {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let resp = daemon.clone().handle(req).await;
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Wait until a job has the given status
+    async fn wait_for(daemon: &Arc<Daemon>, id: u64, status: &str) {
+        for _ in 0..100 {
+            if request(daemon, Method::GET, &format!("/jobs/{id}"), "").await["status"] == status {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Job {id} did not become {status}");
+    }
+
+    /// Server that accepts connections but never answers, so downloads keep running
+    async fn silent_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                connections.push(socket);
+            }
+        });
+        format!("http://{addr}/manifest.mpd")
+    }
+
+    fn config(state_dir: PathBuf) -> DaemonConfig {
+        DaemonConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            state_dir,
+            output: None,
+            fetch: FetchConfig::default(),
+            segments: DownloadSegments::all(),
+        }
+    }
+
+    #[tokio::test]
+    async fn job_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("iglive-daemon-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let url = silent_server().await;
+
+        // Submit and stop a job
+        let daemon = Daemon::open(config(dir.join("state"))).await.unwrap();
+        let job = request(
+            &daemon,
+            Method::POST,
+            "/jobs",
+            &json!({ "url": url }).to_string(),
+        )
+        .await;
+        assert_eq!(job["id"], 1);
+        assert_eq!(job["status"], "running");
+        request(&daemon, Method::POST, "/jobs/1/stop", "").await;
+        wait_for(&daemon, 1, "stopped").await;
+        let stopped = request(&daemon, Method::POST, "/jobs/1/stop", "").await;
+        assert_eq!(stopped["error"], "Job 1 is not running");
+
+        // A job interrupted while running is resumed with the statistics of its segments
+        let segments = dir.join("segments");
+        fs::create_dir_all(&segments).await.unwrap();
+        let store = FsStore::new(&segments);
+        for (media_type, t, source) in [
+            (MediaType::Video, 0, SegmentSource::Past),
+            (MediaType::Video, 2000, SegmentSource::Live),
+            (MediaType::Video, 2000, SegmentSource::Repair),
+            (MediaType::Audio, 0, SegmentSource::Live),
+        ] {
+            store
+                .append_index(&IndexEntry {
+                    media_type,
+                    representation: "rep".to_owned(),
+                    t,
+                    d: None,
+                    pts_start: t,
+                    pts_end: t + 2000,
+                    size: 0,
+                    sha256: String::new(),
+                    source,
+                    downloaded_at: 0,
+                    url: String::new(),
+                })
+                .await
+                .unwrap();
+        }
+        {
+            let mut jobs = daemon.jobs.lock().await;
+            let job = jobs.get_mut(&1).unwrap();
+            job.status = JobStatus::Running;
+            job.dir = Some(segments);
+            job.stats.video_segments = 100;
+        }
+        daemon.save().await;
+
+        let daemon = Daemon::open(config(dir.join("state"))).await.unwrap();
+        daemon.resume().await;
+        let job = request(&daemon, Method::GET, "/jobs/1", "").await;
+        assert_eq!(job["status"], "running");
+        assert_eq!(job["stats"]["video_segments"], 2);
+        assert_eq!(job["stats"]["audio_segments"], 1);
+        assert_eq!(job["stats"]["past_segments"], 1);
+
+        // Segments downloaded again are not counted twice
+        let (tx, rx) = mpsc::unbounded_channel();
+        tx.send(DownloadEvent::Segment {
+            media_type: MediaType::Video,
+            t: 2000,
+            past: false,
+        })
+        .unwrap();
+        tx.send(DownloadEvent::Segment {
+            media_type: MediaType::Video,
+            t: 4000,
+            past: false,
+        })
+        .unwrap();
+        drop(tx);
+        daemon.record_events(1, rx).await;
+        let job = request(&daemon, Method::GET, "/jobs/1", "").await;
+        assert_eq!(job["stats"]["video_segments"], 3);
+
+        request(&daemon, Method::POST, "/jobs/1/stop", "").await;
+        wait_for(&daemon, 1, "stopped").await;
+        let events = request(&daemon, Method::GET, "/jobs/1/events", "").await;
+        let events: Vec<_> = events
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["event"].as_str().unwrap_or("").to_owned())
+            .collect();
+        assert!(events.starts_with(&["submitted".to_owned(), "status".to_owned()]));
+        assert!(events.contains(&"resumed".to_owned()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
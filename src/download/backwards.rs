@@ -1,44 +1,39 @@
 use std::collections::HashSet;
-use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::Result;
 use futures::future;
 use indicatif::ProgressBar;
-use reqwest::{Client, Url};
+use reqwest::Url;
 use tokio::sync::Mutex;
+use tokio::time::Instant;
 
-use super::download_file;
+use super::probe::{Probe, ProbeOutcome};
+use super::{download_file, DownloadEvent};
 use crate::error::IgLiveError;
+use crate::fetch::Fetcher;
 use crate::mpd::Representation;
 use crate::state::State;
+use crate::store::{SegmentKey, SegmentSource, SegmentStore};
 
 pub async fn download_reps_backwards(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     url_base: &Url,
     reps: impl IntoIterator<Item = (&Representation, ProgressBar)>,
     start_frame: usize,
-    dir: impl AsRef<Path> + Send,
 ) -> Result<()> {
     let futures: Vec<_> = reps
         .into_iter()
         .map(|(rep, pb)| {
-            download_backwards(
-                state.clone(),
-                client,
-                url_base,
-                rep,
-                start_frame,
-                dir.as_ref(),
-                pb,
-            )
+            download_backwards(state.clone(), client, store, url_base, rep, start_frame, pb)
         })
         .collect();
     future::join_all(futures)
         .await
         .into_iter()
-        .collect::<Result<_>>()?;
+        .collect::<Result<Vec<_>>>()?;
     Ok(())
 }
 
@@ -49,11 +44,11 @@ pub async fn download_reps_backwards(
 /// time deltas before brute forcing all other deltas.
 async fn download_backwards(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     url_base: &Url,
     rep: &Representation,
     start_frame: usize,
-    dir: impl AsRef<Path>,
     pb: ProgressBar,
 ) -> Result<()> {
     let media_type = rep.media_type();
@@ -71,13 +66,17 @@ async fn download_backwards(
     'outer: loop {
         if latest_t <= start_frame as isize {
             // If reached first frame, finish successfully
+            state
+                .lock()
+                .await
+                .emit(DownloadEvent::PastFinished { media_type });
             pb.finish_with_message("Finished");
             return Ok(());
         }
 
         // Regenerate seed
         let mut v = Vec::from_iter(deltas.clone());
-        v.sort_by(|&(_, a), &(_, b)| b.cmp(&a));
+        v.sort_by_key(|&(_, c)| std::cmp::Reverse(c));
         let new_seed: Vec<_> = v.iter().map(|(d, _)| *d).collect();
 
         let mut lower_bound = 0;
@@ -88,28 +87,68 @@ async fn download_backwards(
                 continue;
             }
 
+            // Stop searching once over the size budget
+            {
+                let mut state = state.lock().await;
+                if let Some(limit) = state.budget.size_limit() {
+                    state.limit_reached(limit);
+                    drop(state);
+                    pb.println(format!(
+                        "Stopped searching for past segments, {limit} reached"
+                    ));
+                    pb.finish_with_message(format!("Stopped at segment {latest_t}"));
+                    return Ok(());
+                }
+            }
+
+            // Stop searching once out of probes
+            if let Some(max) = client.max_probes() {
+                if state.lock().await.probes.requests() >= max {
+                    pb.println(format!(
+                        "Stopped searching for past segments after {max} probes"
+                    ));
+                    pb.finish_with_message(format!("Stopped at segment {latest_t}"));
+                    return Ok(());
+                }
+            }
+
             // Update progress bar
             pb.set_message(format!("Downloaded segment {}, checking {}", latest_t, t));
             pb.tick();
 
             // Try to download segment
             let url = rep.download_url(url_base, t)?;
-            let filename = dir.as_ref().join(
-                url.path_segments()
-                    .ok_or(IgLiveError::InvalidUrl)?
-                    .rev()
-                    .next()
-                    .ok_or(IgLiveError::InvalidUrl)?,
-            );
+            let key = SegmentKey {
+                media_type: media_type.clone(),
+                representation: rep.id.clone(),
+                t: t as usize,
+            };
+            let sent = Instant::now();
             let download_result = download_file(
                 state.clone(),
                 client,
-                rep.media_type(),
-                true,
+                store,
+                &key,
+                SegmentSource::Past,
+                None,
                 &url,
-                filename,
             )
             .await;
+            let outcome = match &download_result {
+                Ok(()) => ProbeOutcome::Found,
+                Err(e) => match e.downcast_ref::<IgLiveError>() {
+                    Some(IgLiveError::StatusNotFound) => ProbeOutcome::NotFound,
+                    Some(IgLiveError::PtsTooEarly) => ProbeOutcome::PtsTooEarly,
+                    _ => ProbeOutcome::Failed,
+                },
+            };
+            state.lock().await.probes.record(&Probe {
+                media_type: media_type.clone(),
+                base_t: latest_t as usize,
+                delta: x,
+                outcome,
+                latency: sent.elapsed().as_millis() as u64,
+            });
             match download_result {
                 Ok(()) => {
                     // Segment exists, continue onto next segment
@@ -117,14 +156,18 @@ async fn download_backwards(
                     // Update local copy
                     *deltas.entry(x).or_insert(0) += 1;
                     // Update global copy
+                    let mut state = state.lock().await;
                     *state
-                        .lock()
-                        .await
                         .deltas
                         .get_mut(&media_type)
                         .unwrap()
                         .entry(x)
                         .or_insert(0) += 1;
+                    state.emit(DownloadEvent::Segment {
+                        media_type: media_type.clone(),
+                        t: t as usize,
+                        past: true,
+                    });
                     continue 'outer;
                 }
                 Err(e) => {
@@ -1,22 +1,23 @@
-use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::Result;
 use futures::future;
 use indicatif::ProgressBar;
-use reqwest::{Client, Url};
+use reqwest::Url;
 use tokio::sync::Mutex;
 use tokio::time::{self, Duration};
 
-use crate::download::download_rep;
+use crate::download::{download_rep, DownloadEvent};
+use crate::fetch::Fetcher;
 use crate::mpd::{MediaType, Mpd, Representation};
 use crate::state::State;
+use crate::store::{SegmentSource, SegmentStore};
 
 pub async fn download_forwards(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     url_base: &Url,
-    dir: impl AsRef<Path> + Send,
     pb: ProgressBar,
 ) -> Result<()> {
     // Set up 2 second interval
@@ -27,6 +28,22 @@ pub async fn download_forwards(
         // Wait for interval
         interval.tick().await;
 
+        // Stop early once a limit is reached
+        let limit = {
+            let mut state = state.lock().await;
+            let limit = state.budget.live_limit();
+            if let Some(limit) = limit {
+                state.limit_reached(limit);
+            }
+            limit
+        };
+        if let Some(limit) = limit {
+            pb.println(format!(
+                "Stopped downloading live segments, {limit} reached"
+            ));
+            break Ok(());
+        }
+
         // Download manifest
         let manifest = Mpd::download_from_url(client, url_base).await?;
         let (video_rep, audio_rep) = manifest.best_media();
@@ -42,15 +59,30 @@ pub async fn download_forwards(
         // Download reps
         let futures: Vec<_> = [video_rep, audio_rep]
             .into_iter()
-            .map(|rep| download_rep(state.clone(), client, rep, url_base, dir.as_ref()))
+            .map(|rep| {
+                download_rep(
+                    state.clone(),
+                    client,
+                    store,
+                    rep,
+                    url_base,
+                    SegmentSource::Live,
+                )
+            })
             .collect();
         future::join_all(futures)
             .await
             .into_iter()
-            .collect::<Result<_>>()?;
+            .collect::<Result<Vec<_>>>()?;
 
-        check_overlap(video_rep, latest_video_t, &pb);
-        check_overlap(audio_rep, latest_audio_t, &pb);
+        for (rep, latest_t) in [(video_rep, latest_video_t), (audio_rep, latest_audio_t)] {
+            if !check_overlap(rep, latest_t, &pb) {
+                state.lock().await.emit(DownloadEvent::MissedSegment {
+                    media_type: rep.media_type(),
+                    t: latest_t,
+                });
+            }
+        }
 
         // Update progress bar
         pb.set_message(format!(
@@ -70,14 +102,15 @@ pub async fn download_forwards(
     ret
 }
 
-fn check_overlap(rep: &Representation, latest_t: usize, pb: &ProgressBar) {
-    if !rep
+fn check_overlap(rep: &Representation, latest_t: usize, pb: &ProgressBar) -> bool {
+    let overlaps = rep
         .segment_template
         .segment_timeline
         .segments
         .iter()
-        .any(|s| s.t == latest_t)
-    {
+        .any(|s| s.t == latest_t);
+    if !overlaps {
         pb.println(format!("Possible missed live segment t={latest_t}"));
     }
+    overlaps
 }
@@ -3,16 +3,19 @@ use std::sync::Arc;
 use anyhow::Result;
 use futures::future;
 use indicatif::ProgressBar;
-use reqwest::{Client, StatusCode, Url};
+use reqwest::{StatusCode, Url};
 use tokio::sync::Mutex;
 
 use crate::error::IgLiveError;
+use crate::fetch::Fetcher;
 use crate::mpd::Representation;
 use crate::state::State;
+use crate::store::SegmentStore;
 
 pub async fn download_reps_init(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     url_base: &Url,
     reps: impl IntoIterator<Item = &Representation>,
     pb: Option<ProgressBar>,
@@ -23,12 +26,12 @@ pub async fn download_reps_init(
 
     let futures: Vec<_> = reps
         .into_iter()
-        .map(|rep| download_init(state.clone(), client, url_base, rep))
+        .map(|rep| download_init(state.clone(), client, store, url_base, rep))
         .collect();
     future::join_all(futures)
         .await
         .into_iter()
-        .collect::<Result<_>>()?;
+        .collect::<Result<Vec<_>>>()?;
 
     if let Some(pb) = pb.as_ref() {
         pb.finish_with_message("Finished");
@@ -39,7 +42,8 @@ pub async fn download_reps_init(
 
 async fn download_init(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     url_base: &Url,
     rep: &Representation,
 ) -> Result<()> {
@@ -49,21 +53,25 @@ async fn download_init(
     }
 
     let url = url_base.join(&rep.segment_template.initialization_path)?;
-    let resp = client.get(url.as_str()).send().await?;
-    if resp.status() == StatusCode::NOT_FOUND {
+    let resp = client.get(&url).await?;
+    if resp.status == StatusCode::NOT_FOUND {
         return Err(IgLiveError::StatusNotFound.into());
     }
-    if !resp.status().is_success() {
-        return Err(IgLiveError::StatusError(resp.status().into(), url.as_str().to_owned()).into());
+    if !resp.status.is_success() {
+        return Err(IgLiveError::StatusError(resp.status.into(), url.as_str().to_owned()).into());
     }
 
-    let buffer: Vec<_> = resp.bytes().await?.into_iter().collect();
+    let buffer = resp.body.to_vec();
+    store.put_init(&media_type, &rep.id, &buffer).await?;
 
-    state
-        .lock()
-        .await
-        .downloaded_init
-        .insert(media_type, buffer);
+    let mut state = state.lock().await;
+    for pipe in &mut state.pipes {
+        pipe.init(&media_type, &buffer);
+    }
+    if let Some(recorder) = &state.recorder {
+        recorder.init(&media_type, &buffer);
+    }
+    state.downloaded_init.insert(media_type, buffer);
 
     Ok(())
 }
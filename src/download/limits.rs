@@ -0,0 +1,165 @@
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+/// When to start a download and when to stop it early
+#[derive(Clone, Debug, Default)]
+pub struct CaptureLimits {
+    /// Wait until this time before downloading anything.
+    pub start_at: Option<SystemTime>,
+
+    /// Stop downloading live segments this long after the download started.
+    pub max_duration: Option<Duration>,
+
+    /// Stop downloading live segments at this time.
+    pub deadline: Option<SystemTime>,
+
+    /// Stop downloading live and past segments once the download directory is larger than this
+    /// many bytes.
+    pub max_size: Option<u64>,
+}
+
+/// Limit that stopped part of a download
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    /// [CaptureLimits::max_duration] passed.
+    Duration,
+    /// [CaptureLimits::deadline] passed.
+    Deadline,
+    /// [CaptureLimits::max_size] was exceeded.
+    Size,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duration => write!(f, "maximum duration"),
+            Self::Deadline => write!(f, "deadline"),
+            Self::Size => write!(f, "size limit"),
+        }
+    }
+}
+
+/// Tracks the limits of a running download
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    live_until: Option<(Instant, Limit)>,
+    max_size: Option<u64>,
+    /// Estimated size of the download directory.
+    size: u64,
+    reached: Vec<Limit>,
+}
+
+impl Budget {
+    /// Start counting from now, with `size` bytes already in the download directory
+    pub fn new(limits: &CaptureLimits, size: u64) -> Self {
+        let now = Instant::now();
+        let duration = limits.max_duration.map(|d| (now + d, Limit::Duration));
+        let deadline = limits.deadline.map(|d| {
+            let remaining = d.duration_since(SystemTime::now()).unwrap_or_default();
+            (now + remaining, Limit::Deadline)
+        });
+        Self {
+            live_until: duration
+                .into_iter()
+                .chain(deadline)
+                .min_by_key(|(at, _)| *at),
+            max_size: limits.max_size,
+            size,
+            reached: vec![],
+        }
+    }
+
+    /// Count bytes written to the download directory
+    pub fn add(&mut self, bytes: usize) {
+        self.size += bytes as u64;
+    }
+
+    /// Correct the estimate with a measured size of the download directory, which also includes
+    /// files that weren't counted like the index or a traffic archive
+    pub fn measured(&mut self, size: u64) {
+        self.size = self.size.max(size);
+    }
+
+    /// Limit that ends the live download, if any was reached
+    pub fn live_limit(&self) -> Option<Limit> {
+        match self.live_until {
+            Some((at, limit)) if Instant::now() >= at => Some(limit),
+            _ => self.size_limit(),
+        }
+    }
+
+    /// [Limit::Size] if the download directory is over its size budget
+    pub fn size_limit(&self) -> Option<Limit> {
+        self.max_size
+            .filter(|&max| self.size > max)
+            .map(|_| Limit::Size)
+    }
+
+    /// Remember that `limit` was reached.
+    /// Returns `false` if it was already reached before.
+    pub fn reach(&mut self, limit: Limit) -> bool {
+        if self.reached.contains(&limit) {
+            return false;
+        }
+        self.reached.push(limit);
+        true
+    }
+}
+
+/// Time between measurements of the download directory while a size limit is set
+pub(crate) const MEASURE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Total size of the files in `dir` and its subdirectories, 0 if it doesn't exist
+pub(crate) fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|e| match e.metadata() {
+            Ok(m) if m.is_dir() => dir_size(&e.path()),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_limits() {
+        let limits = CaptureLimits {
+            max_duration: Some(Duration::from_secs(60)),
+            deadline: Some(SystemTime::now() + Duration::from_secs(3600)),
+            max_size: Some(1000),
+            ..Default::default()
+        };
+        let mut budget = Budget::new(&limits, 400);
+        assert_eq!(budget.live_limit(), None);
+
+        budget.add(600);
+        assert_eq!(budget.size_limit(), None);
+        budget.add(1);
+        assert_eq!(budget.live_limit(), Some(Limit::Size));
+
+        // Measuring only ever raises the estimate
+        let mut budget = Budget::new(&limits, 400);
+        budget.measured(100);
+        assert_eq!(budget.size_limit(), None);
+        budget.measured(1001);
+        assert_eq!(budget.size_limit(), Some(Limit::Size));
+
+        let mut budget = Budget::new(&limits, 0);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(budget.live_limit(), Some(Limit::Duration));
+        assert!(budget.reach(Limit::Duration));
+        assert!(!budget.reach(Limit::Duration));
+    }
+}
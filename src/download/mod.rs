@@ -1,28 +1,45 @@
 mod backwards;
 mod forwards;
 mod initialization;
+mod limits;
+mod pipe;
+mod probe;
 
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::Result;
 use bitflags::bitflags;
 use futures::{future, Future};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::{Client, IntoUrl, StatusCode, Url};
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{IntoUrl, StatusCode, Url};
+use serde::Serialize;
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Mutex;
 
 use self::backwards::download_reps_backwards;
 use self::forwards::download_forwards;
 use self::initialization::download_reps_init;
+pub(crate) use self::limits::Budget;
+use self::limits::{dir_size, MEASURE_INTERVAL};
+pub use self::limits::{CaptureLimits, Limit};
+pub(crate) use self::pipe::Pipe;
+pub use self::pipe::PipeOutput;
+pub(crate) use self::probe::ProbeLog;
 use crate::error::IgLiveError;
-use crate::mpd::{MediaType, Mpd, Representation};
+use crate::fetch::{FetchConfig, Fetched, Fetcher};
+use crate::merge::Recorder;
+pub use crate::mpd::MediaType;
+use crate::mpd::{Mpd, Representation};
 use crate::pts::get_pts;
 use crate::state::State;
+use crate::store::{
+    sha256_hex, unix_time, FsStore, IndexEntry, SegmentKey, SegmentSource, SegmentStore, StreamMeta,
+};
 
 /// Options for download
 #[derive(Clone, Debug)]
@@ -31,8 +48,80 @@ pub struct DownloadConfig {
     /// If `None`, auto generate directory based on live stream ID.
     pub dir: Option<PathBuf>,
 
+    /// Directory to place auto generated directories in.
+    /// If `None`, use the current directory. Ignored if `dir` is set.
+    pub parent_dir: Option<PathBuf>,
+
     /// Choose whether to download live segments or past segments.
     pub segments: DownloadSegments,
+
+    /// Where to store downloaded segments.
+    /// If `None`, store files in the download directory.
+    pub store: Option<Arc<dyn SegmentStore>>,
+
+    /// If set, progress events are sent here.
+    pub events: Option<UnboundedSender<DownloadEvent>>,
+
+    /// Live segments are also written to each of these as one fragmented MP4 stream of video and
+    /// audio. Segments are dropped if the reader falls behind.
+    pub pipes: Vec<PipeOutput>,
+
+    /// Append live segments to [RECORDING_NAME][crate::merge::RECORDING_NAME] in the download
+    /// directory as they arrive, so [merge][crate::merge::merge] afterwards only has to add the
+    /// segments before them.
+    pub incremental: bool,
+
+    /// Write every segment time tried by the past segment search to this JSONL file, with the
+    /// delta, outcome and latency.
+    pub probe_log: Option<PathBuf>,
+
+    /// Only search for past segments back to this point.
+    /// If `None`, search back to the start of the broadcast.
+    pub past_range: Option<PastRange>,
+
+    /// When to start, and when to stop before the stream ends.
+    pub limits: CaptureLimits,
+}
+
+/// Progress notifications sent while downloading
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DownloadEvent {
+    /// Manifest was downloaded and the output directory was created.
+    Started {
+        /// Live stream ID.
+        stream_id: String,
+        /// Output directory.
+        dir: PathBuf,
+    },
+    /// A segment was downloaded.
+    Segment {
+        /// Segment media type.
+        media_type: MediaType,
+        /// Segment time.
+        t: usize,
+        /// Whether the segment was found by the past segment search.
+        past: bool,
+    },
+    /// A live segment was possibly missed.
+    MissedSegment {
+        /// Media type of the missed segment.
+        media_type: MediaType,
+        /// Time of the last segment downloaded before the miss.
+        t: usize,
+    },
+    /// Past segments were downloaded back to the start of the stream.
+    PastFinished {
+        /// Media type of the finished search.
+        media_type: MediaType,
+    },
+    /// A limit was reached, live segments or all segments are no longer downloaded.
+    LimitReached {
+        /// Limit that was reached.
+        limit: Limit,
+    },
+    /// Download finished.
+    Finished,
 }
 
 bitflags! {
@@ -47,6 +136,58 @@ bitflags! {
     }
 }
 
+/// Where to stop searching for past segments, instead of the start of the broadcast
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PastRange {
+    /// Seconds before the live edge when the download started.
+    Last(f64),
+    /// Seconds after the start of the broadcast.
+    FromStart(f64),
+    /// Segment time.
+    FromTime(usize),
+    /// Unix time in seconds, estimated from the broadcast start.
+    FromWallClock(f64),
+}
+
+impl PastRange {
+    /// Segment time of the boundary
+    fn start_t(&self, manifest: &Mpd, meta: &StreamMeta) -> usize {
+        let t = match *self {
+            Self::Last(s) => manifest.live_edge() as f64 - s * 1000.0,
+            Self::FromStart(s) => meta.publish_frame_time as f64 + s * 1000.0,
+            Self::FromTime(t) => t as f64,
+            Self::FromWallClock(u) => {
+                meta.publish_frame_time as f64 + (u - meta.broadcast_start) * 1000.0
+            }
+        };
+        t.max(0.0) as usize
+    }
+}
+
+/// Resources shared between downloads running in the same process
+#[derive(Clone, Debug)]
+pub struct DownloadContext {
+    /// HTTP client used for all requests.
+    pub fetcher: Fetcher,
+
+    /// Progress bars of every download are added here.
+    pub progress: MultiProgress,
+
+    /// Show a header line naming the stream above its progress bars.
+    pub stream_headers: bool,
+}
+
+impl DownloadContext {
+    /// Create a context for a single download.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            fetcher: Fetcher::new(&FetchConfig::default())?,
+            progress: MultiProgress::new(),
+            stream_headers: false,
+        })
+    }
+}
+
 /// Download an IG live stream.
 /// Returns the download output path.
 ///
@@ -54,30 +195,101 @@ bitflags! {
 ///
 /// * `mpd_url` - Full URL of live stream's .mpd manifest.
 pub async fn download(mpd_url: impl IntoUrl, config: DownloadConfig) -> Result<PathBuf> {
-    // Reqwest client
-    let client = Client::builder().timeout(Duration::from_secs(5)).build()?;
+    download_with(&DownloadContext::new()?, mpd_url, config).await
+}
+
+/// Download an IG live stream using shared resources.
+/// Returns the download output path.
+///
+/// # Arguments
+///
+/// * `ctx` - Resources shared with other downloads.
+/// * `mpd_url` - Full URL of live stream's .mpd manifest.
+pub async fn download_with(
+    ctx: &DownloadContext,
+    mpd_url: impl IntoUrl,
+    config: DownloadConfig,
+) -> Result<PathBuf> {
+    let client = &ctx.fetcher;
+    let m = &ctx.progress;
+    let spinner_style =
+        ProgressStyle::with_template("{prefix:.bold.fg.green} {spinner} {wide_msg}")?;
+
+    // Wait for the start time
+    if let Some(start_at) = config.limits.start_at {
+        if let Ok(wait) = start_at.duration_since(SystemTime::now()) {
+            let pb_wait = m.add(ProgressBar::new_spinner());
+            pb_wait.set_style(spinner_style.clone());
+            pb_wait.set_prefix("   Waiting");
+            pb_wait.enable_steady_tick(Duration::from_millis(500));
+            pb_wait.set_message(format!("Starting in {}s", wait.as_secs()));
+            tokio::time::sleep(wait).await;
+            pb_wait.finish_and_clear();
+        }
+    }
 
     // Download manifest
     let url_base = mpd_url.into_url()?;
-    let manifest = Mpd::download_from_url(&client, url_base.clone()).await?;
+    let manifest = Mpd::download_from_url(client, &url_base).await?;
     let (video_rep, audio_rep) = manifest.best_media();
 
     // Create directory
-    let base_dir_name: PathBuf = if let Some(d) = config.dir {
-        d
-    } else {
-        manifest.id.clone().into()
+    let base_dir_name: PathBuf = match (config.dir, config.parent_dir) {
+        (Some(d), _) => d,
+        (None, Some(p)) => p.join(&manifest.id),
+        (None, None) => manifest.id.clone().into(),
+    };
+    let store: Arc<dyn SegmentStore> = match config.store {
+        Some(s) => s,
+        None => {
+            fs::create_dir_all(base_dir_name.join("segments")).await?;
+            Arc::new(FsStore::new(&base_dir_name))
+        }
+    };
+    let store = store.as_ref();
+
+    // Record stream information, keeping the first start time estimate when resuming
+    let meta = match store.get_meta().await? {
+        Some(meta) => meta,
+        None => {
+            let meta = StreamMeta {
+                id: manifest.id.clone(),
+                publish_frame_time: manifest.start_frame,
+                broadcast_start: manifest.broadcast_start(),
+            };
+            store.put_meta(&meta).await?;
+            meta
+        }
+    };
+    let past_start = match config.past_range {
+        Some(range) => range.start_t(&manifest, &meta).max(manifest.start_frame),
+        None => manifest.start_frame,
     };
-    let dir_name = base_dir_name.join("segments");
-    fs::create_dir_all(&dir_name).await?;
 
     // Create state
-    let state = Arc::new(Mutex::new(State::new()));
+    let mut state = State::new(config.events);
+    state.pipes = config.pipes.into_iter().map(Pipe::spawn).collect();
+    if config.incremental {
+        fs::create_dir_all(&base_dir_name).await?;
+        state.recorder = Some(Recorder::spawn(&base_dir_name));
+    }
+    state.probes = ProbeLog::create(config.probe_log.as_deref())?;
+    state.budget = Budget::new(&config.limits, dir_size(&base_dir_name));
+    let state = Arc::new(Mutex::new(state));
+    state.lock().await.emit(DownloadEvent::Started {
+        stream_id: manifest.id.clone(),
+        dir: base_dir_name.clone(),
+    });
 
     // Progress bar
-    let m = MultiProgress::new();
-    let spinner_style =
-        ProgressStyle::with_template("{prefix:.bold.fg.green} {spinner} {wide_msg}")?;
+    if ctx.stream_headers {
+        let pb_header = m.add(ProgressBar::new_spinner());
+        pb_header.set_style(ProgressStyle::with_template(
+            "{prefix:.bold.fg.cyan} {wide_msg}",
+        )?);
+        pb_header.set_prefix("    Stream");
+        pb_header.finish_with_message(format!("{} ({})", manifest.id, base_dir_name.display()));
+    }
 
     // Download initialization
     let pb_init = m.add(ProgressBar::new_spinner());
@@ -86,7 +298,8 @@ pub async fn download(mpd_url: impl IntoUrl, config: DownloadConfig) -> Result<P
     pb_init.set_prefix("      Init");
     download_reps_init(
         state.clone(),
-        &client,
+        client,
+        store,
         &url_base,
         [video_rep, audio_rep],
         Some(pb_init),
@@ -100,16 +313,16 @@ pub async fn download(mpd_url: impl IntoUrl, config: DownloadConfig) -> Result<P
     pb_current.set_prefix("   Current");
     download_reps(
         state.clone(),
-        &client,
+        client,
+        store,
         &url_base,
         [video_rep, audio_rep],
-        &dir_name,
         Some(pb_current),
     )
     .await?;
 
     // Download past and live segments
-    let mut futures: Vec<Pin<Box<dyn Future<Output = Result<()>>>>> = vec![];
+    let mut futures: Vec<Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>> = vec![];
     if config.segments.contains(DownloadSegments::LIVE) {
         // Download live segments
         let pb_forwards = m.add(ProgressBar::new_spinner());
@@ -118,9 +331,9 @@ pub async fn download(mpd_url: impl IntoUrl, config: DownloadConfig) -> Result<P
 
         futures.push(Box::pin(download_forwards(
             state.clone(),
-            &client,
+            client,
+            store,
             &url_base,
-            &dir_name,
             pb_forwards,
         )));
     }
@@ -135,27 +348,63 @@ pub async fn download(mpd_url: impl IntoUrl, config: DownloadConfig) -> Result<P
 
         futures.push(Box::pin(download_reps_backwards(
             state.clone(),
-            &client,
+            client,
+            store,
             &url_base,
             [(video_rep, pb_video), (audio_rep, pb_audio)],
-            manifest.start_frame,
-            &dir_name,
+            past_start,
         )));
     }
-    future::join_all(futures)
-        .await
-        .into_iter()
-        .collect::<Result<_>>()?;
+
+    // Measure the download directory now and then while downloading, to count files besides
+    // segments
+    let measure = async {
+        loop {
+            tokio::time::sleep(MEASURE_INTERVAL).await;
+            let dir = base_dir_name.clone();
+            if let Ok(size) = tokio::task::spawn_blocking(move || dir_size(&dir)).await {
+                state.lock().await.budget.measured(size);
+            }
+        }
+    };
+    let result = tokio::select! {
+        results = future::join_all(futures) => results.into_iter().collect::<Result<Vec<_>>>(),
+        _ = measure, if config.limits.max_size.is_some() => unreachable!(),
+    };
+
+    // Finish writing live streams and the recording
+    let (pipes, recorder) = {
+        let mut state = state.lock().await;
+        (std::mem::take(&mut state.pipes), state.recorder.take())
+    };
+    future::join_all(pipes.into_iter().map(Pipe::finish)).await;
+    if let Some(recorder) = recorder {
+        recorder.finish().await;
+    }
+
+    // Summarize the past segment search, on stderr to keep stdout free for a live pipe
+    let summary = state.lock().await.probes.summary();
+    if !summary.is_empty() {
+        m.suspend(|| {
+            eprintln!("Past segment search for {}:", manifest.id);
+            for line in summary {
+                eprintln!("  {line}");
+            }
+        });
+    }
+    result?;
+
+    state.lock().await.emit(DownloadEvent::Finished);
 
     Ok(base_dir_name)
 }
 
 async fn download_reps(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     url_base: &Url,
     reps: impl IntoIterator<Item = &Representation>,
-    dir: impl AsRef<Path> + Send,
     pb: Option<ProgressBar>,
 ) -> Result<()> {
     if let Some(pb) = pb.as_ref() {
@@ -164,12 +413,30 @@ async fn download_reps(
 
     let futures: Vec<_> = reps
         .into_iter()
-        .map(|rep| download_rep(state.clone(), client, rep, url_base, dir.as_ref()))
+        .map(|rep| {
+            download_rep(
+                state.clone(),
+                client,
+                store,
+                rep,
+                url_base,
+                SegmentSource::Current,
+            )
+        })
         .collect();
     future::join_all(futures)
         .await
         .into_iter()
-        .collect::<Result<_>>()?;
+        .collect::<Result<Vec<_>>>()?;
+
+    // Live and past segments are searched for from the segments downloaded here
+    let downloaded = &state.lock().await.downloaded_segs;
+    let empty = [MediaType::Video, MediaType::Audio]
+        .into_iter()
+        .find(|m| downloaded[m].is_empty());
+    if let Some(media_type) = empty {
+        return Err(IgLiveError::MissingSegments(media_type).into());
+    }
 
     if let Some(pb) = pb.as_ref() {
         pb.finish_with_message("Finished");
@@ -180,99 +447,344 @@ async fn download_reps(
 
 async fn download_rep(
     state: Arc<Mutex<State>>,
-    client: &Client,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
     rep: &Representation,
     url_base: &Url,
-    dir: impl AsRef<Path>,
+    source: SegmentSource,
 ) -> Result<()> {
     let media_type = rep.media_type();
     for segment in &rep.segment_template.segment_timeline.segments {
         let t = segment.t;
 
         // Check if already downloaded
-        if state.lock().await.downloaded_segs[&media_type]
-            .get(&t)
-            .is_some()
-        {
+        if state.lock().await.downloaded_segs[&media_type].contains(&t) {
             continue;
         }
 
         // Try to download segment
         let url = rep.download_url(url_base, t)?;
-        let filename = dir.as_ref().join(
-            url.path_segments()
-                .ok_or(IgLiveError::InvalidUrl)?
-                .rev()
-                .next()
-                .ok_or(IgLiveError::InvalidUrl)?,
-        );
-        download_file(
+        let key = SegmentKey {
+            media_type: media_type.clone(),
+            representation: rep.id.clone(),
+            t,
+        };
+        let result = download_file(
             state.clone(),
             client,
-            rep.media_type(),
-            false,
+            store,
+            &key,
+            source,
+            Some(segment.d),
             &url,
-            filename,
         )
-        .await?;
+        .await;
+        if let Err(e) = result {
+            match e.downcast_ref::<IgLiveError>() {
+                // Rejected segment was quarantined, retry if it is still in the next manifest
+                Some(IgLiveError::TruncatedBody | IgLiveError::CorruptSegment) => continue,
+                _ => return Err(e),
+            }
+        }
 
         // Update state
+        let mut state = state.lock().await;
         state
-            .lock()
-            .await
             .downloaded_segs
             .get_mut(&media_type)
             .unwrap()
             .insert(t);
+        state.emit(DownloadEvent::Segment {
+            media_type: media_type.clone(),
+            t,
+            past: false,
+        });
     }
     Ok(())
 }
 
 async fn download_file(
     state: Arc<Mutex<State>>,
-    client: &Client,
-    media_type: MediaType,
-    check_pts: bool,
+    client: &Fetcher,
+    store: &dyn SegmentStore,
+    key: &SegmentKey,
+    source: SegmentSource,
+    d: Option<usize>,
     url: &Url,
-    path: impl AsRef<Path>,
 ) -> Result<()> {
-    let resp = client.get(url.as_str()).send().await?;
-    if resp.status() == StatusCode::NOT_FOUND {
+    let media_type = &key.media_type;
+    let check_pts = source == SegmentSource::Past;
+    let resp = match source {
+        SegmentSource::Past => client.probe(url).await?,
+        _ => client.get(url).await?,
+    };
+    if resp.status == StatusCode::NOT_FOUND {
         return Err(IgLiveError::StatusNotFound.into());
     }
 
-    if !resp.status().is_success() {
-        return Err(IgLiveError::StatusError(resp.status().into(), url.as_str().to_owned()).into());
+    if !resp.status.is_success() {
+        return Err(IgLiveError::StatusError(resp.status.into(), url.as_str().to_owned()).into());
     }
 
-    // Concat initialization and segment data
-    let mut buffer = Vec::new();
-    buffer
-        .write_all(state.lock().await.downloaded_init.get(&media_type).unwrap())
-        .await?;
-    buffer.write_all(&resp.bytes().await?).await?;
-
-    // Write to file
-    let mut file_buffer = fs::File::create(path).await?;
-    file_buffer.write_all(&buffer).await?;
+    // Validate before storing, rejected data is quarantined instead. A PTS miss is the expected
+    // outcome of a past segment probe, so it isn't kept.
+    let pts = match validate_segment(&state, media_type, check_pts, &resp).await {
+        Ok(pts) => pts,
+        Err(e) => {
+            if let Some(IgLiveError::TruncatedBody | IgLiveError::CorruptSegment) =
+                e.downcast_ref::<IgLiveError>()
+            {
+                store.quarantine(key, &resp.body, &e.to_string()).await?;
+                state.lock().await.budget.add(resp.body.len());
+            }
+            return Err(e);
+        }
+    };
 
-    // Check pts
-    let pts = get_pts(buffer).await?;
-    if check_pts {
-        let target_pts = *state.lock().await.back_pts.get(&media_type).unwrap();
-        if target_pts.abs_diff(pts.1) > 1 {
-            return Err(IgLiveError::PtsTooEarly.into());
+    // Write fragment to store, initialization data is stored separately
+    store.put_segment(key, &resp.body).await?;
+    {
+        let mut state = state.lock().await;
+        state.budget.add(resp.body.len());
+        if matches!(source, SegmentSource::Current | SegmentSource::Live) {
+            for pipe in &mut state.pipes {
+                pipe.fragment(media_type, &resp.body);
+            }
+            if let Some(recorder) = &state.recorder {
+                recorder.append(key, pts, &resp.body);
+                state.budget.add(resp.body.len());
+            }
         }
     }
+    store
+        .append_index(&IndexEntry {
+            media_type: media_type.clone(),
+            representation: key.representation.clone(),
+            t: key.t,
+            d,
+            pts_start: pts.0,
+            pts_end: pts.1,
+            size: resp.body.len(),
+            sha256: sha256_hex(&resp.body),
+            source,
+            downloaded_at: unix_time(),
+            url: url.to_string(),
+        })
+        .await?;
 
     // Update pts
     state
         .lock()
         .await
         .back_pts
-        .entry(media_type)
+        .entry(media_type.clone())
         .and_modify(|p| *p = std::cmp::min(*p, pts.0))
         .or_insert(pts.0);
 
     Ok(())
 }
+
+/// Check that a downloaded segment is complete, decodable, and has the expected PTS.
+/// Returns the segment's start and end PTS.
+async fn validate_segment(
+    state: &Mutex<State>,
+    media_type: &MediaType,
+    check_pts: bool,
+    resp: &Fetched,
+) -> Result<(usize, usize)> {
+    // Check length
+    let content_length = resp
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if content_length.is_some_and(|l| l != resp.body.len()) {
+        return Err(IgLiveError::TruncatedBody.into());
+    }
+
+    // Concat initialization and segment data to check pts
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(state.lock().await.downloaded_init.get(media_type).unwrap());
+    buffer.extend_from_slice(&resp.body);
+    let pts = get_pts(buffer).await?;
+    if check_pts {
+        let target_pts = *state.lock().await.back_pts.get(media_type).unwrap();
+        if target_pts.abs_diff(pts.1) > 1 {
+            return Err(IgLiveError::PtsTooEarly.into());
+        }
+    }
+
+    Ok(pts)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::MemoryStore;
+
+    const MANIFEST: &str = r#"<MPD loapStreamId="123" publishFrameTime="0"><Period>
+        <AdaptationSet><Representation id="v" mimeType="video/mp4" bandwidth="1000">
+            <SegmentTemplate initialization="v/init.mp4" media="v/$Time$.m4s">
+                <SegmentTimeline><S t="0" d="2000"/></SegmentTimeline>
+            </SegmentTemplate>
+        </Representation></AdaptationSet>
+        <AdaptationSet><Representation id="a" mimeType="audio/mp4" bandwidth="100">
+            <SegmentTemplate initialization="a/init.mp4" media="a/$Time$.m4s">
+                <SegmentTimeline><S t="0" d="2000"/></SegmentTimeline>
+            </SegmentTemplate>
+        </Representation></AdaptationSet>
+    </Period></MPD>"#;
+
+    /// URL path, headers and body of a recorded response
+    type Response<'a> = (&'a str, &'a [(&'a str, &'a str)], &'a [u8]);
+
+    /// Write a traffic archive answering each URL with a successful response
+    fn write_traffic(dir: &std::path::Path, responses: &[Response]) {
+        std::fs::create_dir_all(dir.join("bodies")).unwrap();
+        let mut log = String::new();
+        for (url, headers, body) in responses {
+            let name = sha256_hex(body);
+            std::fs::write(dir.join("bodies").join(&name), body).unwrap();
+            let exchange = json!({
+                "at": 0,
+                "url": format!("http://localhost/{url}"),
+                "latency": 0,
+                "status": 200,
+                "headers": headers,
+                "body": name,
+                "error": null,
+            });
+            log += &format!("{exchange}\n");
+        }
+        std::fs::write(dir.join("traffic.jsonl"), log).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_to_memory_store() {
+        let dir = std::env::temp_dir().join(format!("iglive-download-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        write_traffic(
+            &dir,
+            &[
+                ("manifest.mpd", &[], MANIFEST.as_bytes()),
+                ("v/init.mp4", &[], b"video init"),
+                ("a/init.mp4", &[], b"audio init"),
+                ("v/0.m4s", &[("content-length", "100")], b"truncated"),
+            ],
+        );
+        let client = Fetcher::new(&FetchConfig {
+            replay_traffic: Some(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        let store = MemoryStore::new();
+        let state = Arc::new(Mutex::new(State::new(None)));
+        let url_base = Url::parse("http://localhost/manifest.mpd").unwrap();
+
+        // Initialization data is stored once per representation
+        let manifest = Mpd::download_from_url(&client, &url_base).await.unwrap();
+        let (video_rep, audio_rep) = manifest.best_media();
+        download_reps_init(
+            state.clone(),
+            &client,
+            &store,
+            &url_base,
+            [video_rep, audio_rep],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            store.get_init(&MediaType::Video, "v").await.unwrap(),
+            Some(b"video init".to_vec())
+        );
+
+        // Rejected segments are quarantined, missing ones leave no trace
+        let key = |t| SegmentKey {
+            media_type: MediaType::Video,
+            representation: "v".to_owned(),
+            t,
+        };
+        for (t, expected) in [(0, "Response body"), (2000, "404")] {
+            let url = video_rep.download_url(&url_base, t as isize).unwrap();
+            let err = download_file(
+                state.clone(),
+                &client,
+                &store,
+                &key(t),
+                SegmentSource::Live,
+                None,
+                &url,
+            )
+            .await
+            .unwrap_err();
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+        let quarantined = store.quarantined().await;
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].0, key(0));
+        assert_eq!(quarantined[0].1, b"truncated");
+        assert!(store.list_segments().await.unwrap().is_empty());
+        assert!(store.read_index().await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn every_current_segment_rejected() {
+        let dir = std::env::temp_dir().join(format!("iglive-rejected-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let truncated: &[(&str, &str)] = &[("content-length", "100")];
+        write_traffic(
+            &dir,
+            &[
+                ("manifest.mpd", &[], MANIFEST.as_bytes()),
+                ("v/init.mp4", &[], b"video init"),
+                ("a/init.mp4", &[], b"audio init"),
+                ("v/0.m4s", truncated, b"truncated"),
+                ("a/0.m4s", truncated, b"truncated"),
+            ],
+        );
+        let client = Fetcher::new(&FetchConfig {
+            replay_traffic: Some(dir.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        let store = MemoryStore::new();
+        let state = Arc::new(Mutex::new(State::new(None)));
+        let url_base = Url::parse("http://localhost/manifest.mpd").unwrap();
+        let manifest = Mpd::download_from_url(&client, &url_base).await.unwrap();
+        let (video_rep, audio_rep) = manifest.best_media();
+        download_reps_init(
+            state.clone(),
+            &client,
+            &store,
+            &url_base,
+            [video_rep, audio_rep],
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Nothing to continue live or past downloads from
+        let err = download_reps(
+            state.clone(),
+            &client,
+            &store,
+            &url_base,
+            [video_rep, audio_rep],
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IgLiveError>(),
+            Some(IgLiveError::MissingSegments(MediaType::Video))
+        ));
+        assert_eq!(store.quarantined().await.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
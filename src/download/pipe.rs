@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tokio::fs::OpenOptions;
+use tokio::io::{self, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::error::IgLiveError;
+use crate::mp4::{mux_init, retrack_fragments};
+use crate::mpd::MediaType;
+
+/// Number of segments buffered for a slow reader before new segments are dropped
+const PIPE_BUFFER: usize = 30;
+
+/// Time to wait before restarting a failed restream
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Where to write live segments as a fragmented MP4 stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipeOutput {
+    /// Standard output.
+    Stdout,
+    /// A file or named pipe, opened when the first segment is ready.
+    Path(PathBuf),
+    /// An RTMP or SRT URL, pushed to by ffmpeg without re-encoding.
+    /// ffmpeg is restarted if it exits.
+    Restream(String),
+}
+
+enum PipeMessage {
+    Init(MediaType, Vec<u8>),
+    Fragment(MediaType, Vec<u8>),
+}
+
+/// Sends initialization data and live segments to the task writing the stream
+pub struct Pipe {
+    tx: Sender<PipeMessage>,
+    task: JoinHandle<()>,
+    dropped: usize,
+}
+
+impl Pipe {
+    /// Start writing to `output`
+    pub fn spawn(output: PipeOutput) -> Self {
+        let (tx, rx) = mpsc::channel(PIPE_BUFFER);
+        let task = tokio::spawn(async move {
+            match write_stream(output, rx).await {
+                Ok(()) => {}
+                Err(e)
+                    if e.downcast_ref::<io::Error>()
+                        .is_some_and(|e| e.kind() == ErrorKind::BrokenPipe) =>
+                {
+                    eprintln!("Live stream reader closed the pipe");
+                }
+                Err(e) => eprintln!("Stopped writing live stream: {e}"),
+            }
+        });
+        Self {
+            tx,
+            task,
+            dropped: 0,
+        }
+    }
+
+    /// Send a representation's initialization data
+    pub fn init(&mut self, media_type: &MediaType, data: &[u8]) {
+        self.send(PipeMessage::Init(media_type.clone(), data.to_vec()));
+    }
+
+    /// Send a live segment
+    pub fn fragment(&mut self, media_type: &MediaType, data: &[u8]) {
+        self.send(PipeMessage::Fragment(media_type.clone(), data.to_vec()));
+    }
+
+    /// Never wait for the reader, so downloading isn't slowed down. Segments are dropped instead
+    /// while the buffer is full.
+    fn send(&mut self, message: PipeMessage) {
+        match self.tx.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                eprintln!(
+                    "WARNING: Live stream reader is too slow, dropped {} segments",
+                    self.dropped
+                );
+            }
+            // Writing already stopped
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Wait for buffered segments to be written
+    pub async fn finish(self) {
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+}
+
+/// Write the muxed initialization data once both tracks' are received, then every fragment.
+/// A restream is started again with the initialization data when ffmpeg fails.
+async fn write_stream(output: PipeOutput, mut rx: Receiver<PipeMessage>) -> Result<()> {
+    let mut writer: Option<Writer> = None;
+    let mut inits = HashMap::new();
+    let mut init = None;
+    let mut sequence = 0;
+    let mut restart_at: Option<Instant> = None;
+    while let Some(message) = rx.recv().await {
+        let data = match message {
+            PipeMessage::Init(media_type, data) => {
+                inits.entry(media_type).or_insert(data);
+                if let (None, Some(video), Some(audio)) = (
+                    &init,
+                    inits.get(&MediaType::Video),
+                    inits.get(&MediaType::Audio),
+                ) {
+                    init = Some(mux_init(video, audio).ok_or(IgLiveError::MissingInit)?);
+                }
+                continue;
+            }
+            PipeMessage::Fragment(media_type, data) => {
+                let track_id = match media_type {
+                    MediaType::Audio => 2,
+                    _ => 1,
+                };
+                retrack_fragments(&data, track_id, &mut sequence)
+                    .ok_or(IgLiveError::CorruptSegment)?
+            }
+        };
+        let Some(init) = &init else {
+            continue;
+        };
+        if writer.is_none() && restart_at.is_some_and(|t| t > Instant::now()) {
+            continue;
+        }
+
+        let result = async {
+            let w = match &mut writer {
+                Some(w) => w,
+                None => {
+                    let mut w = Writer::open(&output).await?;
+                    w.write(init).await?;
+                    writer.insert(w)
+                }
+            };
+            w.write(&data).await
+        }
+        .await;
+        if let Err(e) = result {
+            let PipeOutput::Restream(url) = &output else {
+                return Err(e);
+            };
+            eprintln!(
+                "Restream to {url} failed: {e}, restarting in {}s",
+                RESTART_DELAY.as_secs()
+            );
+            writer = None;
+            restart_at = Some(Instant::now() + RESTART_DELAY);
+        }
+    }
+
+    if let Some(writer) = writer {
+        writer.finish().await?;
+    }
+    Ok(())
+}
+
+/// Open stream output
+struct Writer {
+    inner: Box<dyn AsyncWrite + Send + Unpin>,
+    /// ffmpeg process reading the stream, if restreaming.
+    ffmpeg: Option<Child>,
+}
+
+impl Writer {
+    async fn open(output: &PipeOutput) -> Result<Self> {
+        let (inner, ffmpeg): (Box<dyn AsyncWrite + Send + Unpin>, _) = match output {
+            PipeOutput::Stdout => (Box::new(io::stdout()), None),
+            // Opening a named pipe waits for a reader
+            PipeOutput::Path(path) => (
+                Box::new(
+                    OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(path)
+                        .await?,
+                ),
+                None,
+            ),
+            PipeOutput::Restream(url) => {
+                let mut child = restream_command(url)
+                    .stdin(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()?;
+
+                // Show ffmpeg's errors
+                let stderr = child.stderr.take().unwrap();
+                tokio::spawn(async move {
+                    let mut lines = BufReader::new(stderr).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        eprintln!("ffmpeg: {line}");
+                    }
+                });
+                (Box::new(child.stdin.take().unwrap()), Some(child))
+            }
+        };
+        Ok(Self { inner, ffmpeg })
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(status) = self
+            .ffmpeg
+            .as_mut()
+            .map(Child::try_wait)
+            .transpose()?
+            .flatten()
+        {
+            bail!("ffmpeg exited with {status}");
+        }
+        self.inner.write_all(data).await?;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    /// Close the stream and wait for ffmpeg to finish sending it
+    async fn finish(mut self) -> Result<()> {
+        self.inner.shutdown().await?;
+        drop(self.inner);
+        if let Some(mut ffmpeg) = self.ffmpeg {
+            ffmpeg.wait().await?;
+        }
+        Ok(())
+    }
+}
+
+/// ffmpeg command pushing a fragmented MP4 stream from stdin to `url` without re-encoding
+fn restream_command(url: &str) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0"])
+        .args(["-map", "0", "-c", "copy"]);
+    let scheme = url.split_once("://").map_or("", |(s, _)| s);
+    match scheme {
+        "rtmp" | "rtmps" => {
+            cmd.args(["-f", "flv"]);
+        }
+        "srt" | "udp" | "tcp" => {
+            cmd.args(["-f", "mpegts"]);
+        }
+        _ => {}
+    }
+    cmd.arg(url);
+    cmd
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn restream_formats() {
+        let args = |url| {
+            restream_command(url)
+                .as_std()
+                .get_args()
+                .map(|a| a.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(args("rtmp://localhost/live/key").ends_with(&[
+            "-f".to_owned(),
+            "flv".to_owned(),
+            "rtmp://localhost/live/key".to_owned()
+        ]));
+        assert!(args("srt://localhost:9000").contains(&"mpegts".to_owned()));
+        assert!(!args("out.mkv").contains(&"-f".to_owned()));
+    }
+}
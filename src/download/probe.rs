@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::mpd::MediaType;
+
+/// Number of most common deltas listed in the summary
+const SUMMARY_DELTAS: usize = 5;
+
+/// Result of trying one candidate segment time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ProbeOutcome {
+    Found,
+    NotFound,
+    PtsTooEarly,
+    Failed,
+}
+
+/// One candidate segment time tried by the past segment search
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Probe {
+    pub media_type: MediaType,
+    /// Time of the earliest segment found so far.
+    pub base_t: usize,
+    /// Offset subtracted from `base_t`.
+    pub delta: isize,
+    pub outcome: ProbeOutcome,
+    /// Milliseconds until the request finished.
+    pub latency: u64,
+}
+
+#[derive(Debug, Default)]
+struct ProbeStats {
+    requests: usize,
+    not_found: usize,
+    pts_too_early: usize,
+    failed: usize,
+    latency: u64,
+    /// Number of segments found with each delta.
+    hits: HashMap<isize, usize>,
+}
+
+/// Writes every probe to a JSONL file and counts outcomes for the summary
+#[derive(Debug, Default)]
+pub(crate) struct ProbeLog {
+    file: Option<File>,
+    stats: BTreeMap<MediaType, ProbeStats>,
+}
+
+impl ProbeLog {
+    /// Log probes to `path` if set, otherwise only count them
+    pub fn create(path: Option<&Path>) -> Result<Self> {
+        Ok(Self {
+            file: path.map(File::create).transpose()?,
+            stats: BTreeMap::new(),
+        })
+    }
+
+    pub fn record(&mut self, probe: &Probe) {
+        let stats = self.stats.entry(probe.media_type.clone()).or_default();
+        stats.requests += 1;
+        stats.latency += probe.latency;
+        match probe.outcome {
+            ProbeOutcome::Found => *stats.hits.entry(probe.delta).or_default() += 1,
+            ProbeOutcome::NotFound => stats.not_found += 1,
+            ProbeOutcome::PtsTooEarly => stats.pts_too_early += 1,
+            ProbeOutcome::Failed => stats.failed += 1,
+        }
+
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let result = serde_json::to_vec(probe)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                Ok(file.write_all(&line)?)
+            });
+        if let Err(e) = result {
+            eprintln!("WARNING: Stopped writing probe log: {e}");
+            self.file = None;
+        }
+    }
+
+    /// Number of probes of every media type
+    pub fn requests(&self) -> usize {
+        self.stats.values().map(|s| s.requests).sum()
+    }
+
+    /// Lines describing how well the search went for each media type and overall
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![];
+        let mut total = ProbeStats::default();
+        for (media_type, stats) in &self.stats {
+            lines.push(format!("{media_type:?}: {}", describe(stats)));
+            total.requests += stats.requests;
+            total.not_found += stats.not_found;
+            total.pts_too_early += stats.pts_too_early;
+            total.failed += stats.failed;
+            total.latency += stats.latency;
+            for (delta, count) in &stats.hits {
+                *total.hits.entry(*delta).or_default() += count;
+            }
+        }
+        if self.stats.len() > 1 {
+            lines.push(format!("Total: {}", describe(&total)));
+        }
+        lines
+    }
+}
+
+fn describe(stats: &ProbeStats) -> String {
+    let found: usize = stats.hits.values().sum();
+    let mut deltas: Vec<_> = stats.hits.iter().collect();
+    deltas.sort_by_key(|&(delta, count)| (std::cmp::Reverse(*count), *delta));
+    let deltas: Vec<_> = deltas
+        .iter()
+        .take(SUMMARY_DELTAS)
+        .map(|(delta, count)| format!("{delta} ({count})"))
+        .collect();
+    format!(
+        "{} requests, {found} segments found ({:.1}% hit rate, {:.1} requests per segment), \
+         {} not found, {} PTS too early, {} failed, {:.0}ms average, top deltas: {}",
+        stats.requests,
+        100.0 * found as f64 / stats.requests.max(1) as f64,
+        stats.requests as f64 / found.max(1) as f64,
+        stats.not_found,
+        stats.pts_too_early,
+        stats.failed,
+        stats.latency as f64 / stats.requests.max(1) as f64,
+        if deltas.is_empty() {
+            "none".to_owned()
+        } else {
+            deltas.join(", ")
+        }
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn probe_summary() {
+        let mut log = ProbeLog::create(None).unwrap();
+        let probe = |media_type, delta, outcome| Probe {
+            media_type,
+            base_t: 10000,
+            delta,
+            outcome,
+            latency: 10,
+        };
+        log.record(&probe(MediaType::Video, 2000, ProbeOutcome::NotFound));
+        log.record(&probe(MediaType::Video, 2001, ProbeOutcome::Found));
+        log.record(&probe(MediaType::Audio, 1999, ProbeOutcome::PtsTooEarly));
+        log.record(&probe(MediaType::Audio, 2000, ProbeOutcome::Found));
+
+        let summary = log.summary();
+        assert_eq!(summary.len(), 3);
+        assert!(summary[0].starts_with("Video: 2 requests, 1 segments found (50.0% hit rate"));
+        assert!(summary[1].contains("1 PTS too early"));
+        assert!(summary[2].contains("top deltas: 2000 (1), 2001 (1)"));
+    }
+}
@@ -1,15 +1,25 @@
 use thiserror::Error;
 
+use crate::mpd::MediaType;
+
 #[derive(Error, Debug)]
 pub enum IgLiveError {
-    #[error("Invalid URL")]
-    InvalidUrl,
     #[error("Received status code 404received")]
     StatusNotFound,
     #[error("Received status code {0}, url: {1}")]
     StatusError(u16, String),
-    #[error("Missing init")]
+    #[error("ffmpeg failed")]
     FfmpegFail,
+    #[error("Missing init")]
+    MissingInit,
     #[error("PTS too early")]
     PtsTooEarly,
+    #[error("Response body is shorter than its Content-Length")]
+    TruncatedBody,
+    #[error("Segment could not be parsed")]
+    CorruptSegment,
+    #[error("Segment archive is corrupt")]
+    CorruptArchive,
+    #[error("No {0:?} segment of the current manifest could be downloaded")]
+    MissingSegments(MediaType),
 }
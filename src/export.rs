@@ -0,0 +1,705 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use quick_xml::escape::escape;
+use serde::Serialize;
+use tokio::fs;
+
+use crate::error::IgLiveError;
+use crate::mp4::{init_info, split_init, InitInfo};
+use crate::mpd::MediaType;
+use crate::pts::get_pts;
+use crate::store::{index_by_key, FsStore, IndexEntry, SegmentKey, SegmentStore, StreamMeta};
+
+/// File name of the exported DASH manifest
+pub(crate) const MPD_NAME: &str = "manifest.mpd";
+
+/// File name of the exported HLS master playlist
+pub(crate) const MASTER_PLAYLIST_NAME: &str = "master.m3u8";
+
+/// Options for export
+#[derive(Clone, Debug, Default)]
+pub struct ExportConfig {
+    /// Write a DASH manifest.
+    pub dash: bool,
+
+    /// Write HLS playlists.
+    pub hls: bool,
+
+    /// URL prefix for initialization data and media segments.
+    /// If `None`, they are referenced relative to the download directory.
+    pub base_url: Option<String>,
+}
+
+/// Result of export
+#[derive(Clone, Debug, Serialize)]
+pub struct ExportReport {
+    /// Path of the DASH manifest, if written.
+    pub mpd: Option<PathBuf>,
+
+    /// Path of the HLS master playlist, if written.
+    pub m3u8: Option<PathBuf>,
+
+    /// Number of places a track's timeline is not continuous.
+    pub discontinuities: usize,
+}
+
+/// Representation of one media type written to the manifests
+pub(crate) struct ExportTrack {
+    pub(crate) media_type: MediaType,
+    representation: String,
+    info: InitInfo,
+    init_uri: String,
+    /// Segment times, used for wall-clock times.
+    times: Vec<usize>,
+    uris: Vec<String>,
+    timeline: Vec<TimelineSegment>,
+    /// Highest bitrate of any segment in bit/s.
+    peak_bandwidth: u64,
+    /// Bitrate over the whole track in bit/s.
+    average_bandwidth: u64,
+}
+
+/// Segment in a track's timeline, in PTS units
+#[derive(Debug, PartialEq)]
+struct TimelineSegment {
+    t: usize,
+    d: usize,
+    /// The previous segment doesn't end where this one starts.
+    discontinuity: bool,
+}
+
+/// Write a static DASH manifest and/or HLS playlists for a live stream downloaded by
+/// [download][crate::download::download], so it can be played without merging.
+///
+/// The manifests are written to `dir` and reference the initialization data and media segments
+/// already there. The representation with the most segments of each media type is exported.
+/// Places where segments are missing are left as gaps in the DASH timeline and marked as
+/// discontinuities in the HLS playlists.
+///
+/// # Arguments
+///
+/// `dir` - Directory containing downloaded video and audio segments.
+pub async fn export(dir: impl AsRef<Path>, config: &ExportConfig) -> Result<ExportReport> {
+    let dir = dir.as_ref();
+    let mut package = Package::new(dir, config.base_url.as_deref());
+    let tracks = package.tracks().await?;
+    let meta = package.store.get_meta().await?;
+
+    let mpd = if config.dash {
+        let path = dir.join(MPD_NAME);
+        std::fs::write(&path, mpd(&tracks, None))?;
+        println!("DASH manifest written to {:?}", path);
+        Some(path)
+    } else {
+        None
+    };
+    let m3u8 = if config.hls {
+        for track in &tracks {
+            std::fs::write(
+                dir.join(playlist_name(&track.media_type)),
+                media_playlist(track, meta.as_ref()),
+            )?;
+        }
+        let path = dir.join(MASTER_PLAYLIST_NAME);
+        std::fs::write(&path, master_playlist(&tracks))?;
+        println!("HLS playlist written to {:?}", path);
+        Some(path)
+    } else {
+        None
+    };
+
+    Ok(ExportReport {
+        mpd,
+        m3u8,
+        discontinuities: tracks
+            .iter()
+            .flat_map(|t| &t.timeline)
+            .filter(|s| s.discontinuity)
+            .count(),
+    })
+}
+
+/// Reads the tracks of a download directory, keeping the PTS of probed segments between reads
+pub(crate) struct Package {
+    dir: PathBuf,
+    pub(crate) store: FsStore,
+    base_url: String,
+    /// Size and PTS of segments without index entries.
+    probed: HashMap<SegmentKey, (usize, (usize, usize))>,
+    /// Representations already warned about being skipped.
+    skipped: HashSet<(MediaType, String)>,
+}
+
+impl Package {
+    /// Read `dir`, referencing its files relative to it or prefixed with `base_url`
+    pub(crate) fn new(dir: &Path, base_url: Option<&str>) -> Self {
+        let base_url = match base_url {
+            Some(url) if !url.ends_with('/') => format!("{url}/"),
+            Some(url) => url.to_owned(),
+            None => String::new(),
+        };
+        Self {
+            dir: dir.to_owned(),
+            store: FsStore::new(dir),
+            base_url,
+            probed: HashMap::new(),
+            skipped: HashSet::new(),
+        }
+    }
+
+    /// Tracks with the representation with the most segments of each media type
+    pub(crate) async fn tracks(&mut self) -> Result<Vec<ExportTrack>> {
+        // Read the index after listing, so it covers as many of the listed segments as possible
+        let segments = self.store.list_segments().await?;
+        let index = index_by_key(self.store.read_index().await?);
+
+        let mut tracks = vec![];
+        for media_type in [MediaType::Video, MediaType::Audio] {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for key in segments.iter().filter(|k| k.media_type == media_type) {
+                *counts.entry(&key.representation).or_default() += 1;
+            }
+            let Some((&representation, _)) = counts.iter().max_by_key(|(_, &count)| count) else {
+                continue;
+            };
+            for (&rep, count) in counts.iter().filter(|(&r, _)| r != representation) {
+                if self.skipped.insert((media_type.clone(), rep.to_owned())) {
+                    eprintln!(
+                        "WARNING: Skipping {count} segments of {media_type:?} representation {rep}"
+                    );
+                }
+            }
+
+            let keys: Vec<_> = segments
+                .iter()
+                .filter(|k| k.media_type == media_type && k.representation == representation)
+                .collect();
+            tracks.push(self.track(&index, &keys).await?);
+        }
+        if tracks.is_empty() {
+            bail!("No segments to export");
+        }
+        Ok(tracks)
+    }
+
+    /// Collect the timeline and file locations of one representation's segments
+    async fn track(
+        &mut self,
+        index: &HashMap<SegmentKey, IndexEntry>,
+        keys: &[&SegmentKey],
+    ) -> Result<ExportTrack> {
+        let first = keys[0];
+        let init = self
+            .store
+            .get_init(&first.media_type, &first.representation)
+            .await?
+            .ok_or_else(|| {
+                anyhow!(
+                    "No initialization data for {:?} representation {}, run migrate if it was \
+                     downloaded by an older version",
+                    first.media_type,
+                    first.representation
+                )
+            })?;
+        let info = init_info(&init).ok_or(IgLiveError::CorruptSegment)?;
+
+        let mut spans = vec![];
+        let mut sizes = vec![];
+        let mut uris = vec![];
+        for &key in keys {
+            // Use recorded PTS if available, otherwise probe the segment
+            let path = self.store.segment_file(key).await;
+            let size = fs::metadata(&path).await?.len() as usize;
+            let span = match (index.get(key), self.probed.get(key)) {
+                (Some(e), _) if e.size == size => (e.pts_start, e.pts_end),
+                (_, Some(&(probed_size, span))) if probed_size == size => span,
+                _ => {
+                    let data = fs::read(&path).await?;
+                    if split_init(&data).is_some() {
+                        bail!("{:?} contains initialization data, run migrate first", path);
+                    }
+                    let span = get_pts([init.clone(), data].concat()).await?;
+                    self.probed.insert(key.clone(), (size, span));
+                    span
+                }
+            };
+            spans.push(span);
+            sizes.push(size);
+            uris.push(self.uri(&path));
+        }
+
+        let timeline = timeline(&spans);
+        let timescale = info.timescale as u64;
+        let peak_bandwidth = timeline
+            .iter()
+            .zip(&sizes)
+            .filter(|(s, _)| s.d > 0)
+            .map(|(s, &size)| size as u64 * 8 * timescale / s.d as u64)
+            .max()
+            .unwrap_or(0);
+        let duration: usize = timeline.iter().map(|s| s.d).sum();
+        let average_bandwidth =
+            sizes.iter().sum::<usize>() as u64 * 8 * timescale / duration.max(1) as u64;
+
+        Ok(ExportTrack {
+            media_type: first.media_type.clone(),
+            representation: first.representation.clone(),
+            init_uri: self.uri(
+                &self
+                    .store
+                    .init_path(&first.media_type, &first.representation),
+            ),
+            info,
+            times: keys.iter().map(|k| k.t).collect(),
+            uris,
+            timeline,
+            peak_bandwidth,
+            average_bandwidth,
+        })
+    }
+
+    /// URI of a file in the download directory
+    fn uri(&self, path: &Path) -> String {
+        let path = path.strip_prefix(&self.dir).unwrap_or(path);
+        format!(
+            "{}{}",
+            self.base_url,
+            path.to_string_lossy().replace('\\', "/")
+        )
+    }
+}
+
+impl ExportTrack {
+    /// End of the last segment in seconds
+    pub(crate) fn end(&self) -> f64 {
+        let last = &self.timeline[self.timeline.len() - 1];
+        (last.t + last.d) as f64 / self.info.timescale as f64
+    }
+}
+
+/// Build a timeline from the start and end PTS of each segment.
+/// Segments are shortened if they overlap the next one.
+fn timeline(spans: &[(usize, usize)]) -> Vec<TimelineSegment> {
+    spans
+        .iter()
+        .enumerate()
+        .map(|(i, &(start, end))| {
+            let end = match spans.get(i + 1) {
+                Some(&(next, _)) if next > start => end.min(next),
+                _ => end,
+            };
+            TimelineSegment {
+                t: start,
+                d: end.saturating_sub(start),
+                discontinuity: i > 0 && spans[i - 1].1 != start,
+            }
+        })
+        .collect()
+}
+
+/// `S` elements of a DASH `SegmentTimeline`, only giving `t` after a gap and repeating segments
+/// of the same duration with `r`
+fn segment_timeline(timeline: &[TimelineSegment]) -> Vec<String> {
+    let mut elements: Vec<(Option<usize>, usize, usize)> = vec![];
+    let mut next_t = None;
+    for s in timeline {
+        match elements.last_mut() {
+            Some((_, d, r)) if next_t == Some(s.t) && *d == s.d => *r += 1,
+            _ => elements.push(((next_t != Some(s.t)).then_some(s.t), s.d, 0)),
+        }
+        next_t = Some(s.t + s.d);
+    }
+    elements
+        .into_iter()
+        .map(|(t, d, r)| {
+            let mut element = "<S".to_owned();
+            if let Some(t) = t {
+                element.push_str(&format!(" t=\"{t}\""));
+            }
+            element.push_str(&format!(" d=\"{d}\""));
+            if r > 0 {
+                element.push_str(&format!(" r=\"{r}\""));
+            }
+            element + "/>"
+        })
+        .collect()
+}
+
+/// DASH manifest with one adaptation set per track.
+/// If `availability_start` is given, the manifest is dynamic for players to reload while segments
+/// are added, with PTS 0 at that time. Otherwise it is static and starts at the earliest segment.
+pub(crate) fn mpd(tracks: &[ExportTrack], availability_start: Option<DateTime<Utc>>) -> String {
+    // Start and end of the presentation in seconds
+    let start = tracks
+        .iter()
+        .map(|t| t.timeline[0].t as f64 / t.info.timescale as f64)
+        .fold(f64::INFINITY, f64::min);
+    let end = tracks.iter().map(ExportTrack::end).fold(0.0, f64::max);
+    let duration = end - start;
+    let max_segment = tracks
+        .iter()
+        .flat_map(|t| {
+            t.timeline
+                .iter()
+                .map(|s| s.d as f64 / t.info.timescale as f64)
+        })
+        .fold(0.0, f64::max);
+
+    let mut mpd = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let timing = match availability_start {
+        // Segments stay available for the whole download
+        Some(availability_start) => format!(
+            "type=\"dynamic\" availabilityStartTime=\"{}\" publishTime=\"{}\" \
+             minimumUpdatePeriod=\"PT{:.3}S\"",
+            availability_start.to_rfc3339_opts(SecondsFormat::Millis, true),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            max_segment.ceil()
+        ),
+        None => format!("type=\"static\" mediaPresentationDuration=\"PT{duration:.3}S\""),
+    };
+    mpd.push_str(&format!(
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" \
+         profiles=\"urn:mpeg:dash:profile:full:2011\" {timing} minBufferTime=\"PT{:.3}S\">\n",
+        max_segment.ceil()
+    ));
+    mpd.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+    for (i, track) in tracks.iter().enumerate() {
+        let content_type = match track.media_type {
+            MediaType::Audio => "audio",
+            _ => "video",
+        };
+        mpd.push_str(&format!(
+            "    <AdaptationSet id=\"{i}\" contentType=\"{content_type}\" \
+             mimeType=\"{content_type}/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\n"
+        ));
+
+        let mut representation = format!(
+            "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\"",
+            escape(&track.representation),
+            escape(&track.info.codec),
+            track.peak_bandwidth
+        );
+        if let (Some(w), Some(h)) = (track.info.width, track.info.height) {
+            representation.push_str(&format!(" width=\"{w}\" height=\"{h}\""));
+        }
+        mpd.push_str(&(representation + ">\n"));
+
+        // Start every track at the same presentation time. A dynamic manifest keeps starting at
+        // PTS 0, so past segments found later don't move the live segments.
+        let timescale = track.info.timescale;
+        let offset = match availability_start {
+            Some(_) => 0,
+            None => (start * timescale as f64).round() as usize,
+        };
+        mpd.push_str(&format!(
+            "        <SegmentList timescale=\"{timescale}\" presentationTimeOffset=\"{offset}\">\n"
+        ));
+        mpd.push_str(&format!(
+            "          <Initialization sourceURL=\"{}\"/>\n",
+            escape(&track.init_uri)
+        ));
+        mpd.push_str("          <SegmentTimeline>\n");
+        for element in segment_timeline(&track.timeline) {
+            mpd.push_str(&format!("            {element}\n"));
+        }
+        mpd.push_str("          </SegmentTimeline>\n");
+        for uri in &track.uris {
+            mpd.push_str(&format!(
+                "          <SegmentURL media=\"{}\"/>\n",
+                escape(uri)
+            ));
+        }
+        mpd.push_str("        </SegmentList>\n");
+        mpd.push_str("      </Representation>\n");
+        mpd.push_str("    </AdaptationSet>\n");
+    }
+    mpd.push_str("  </Period>\n");
+    mpd.push_str("</MPD>\n");
+    mpd
+}
+
+/// File name of a track's HLS media playlist
+pub(crate) fn playlist_name(media_type: &MediaType) -> &'static str {
+    match media_type {
+        MediaType::Audio => "audio.m3u8",
+        _ => "video.m3u8",
+    }
+}
+
+/// HLS media playlist of one track with fMP4 segments, ended for playback on demand.
+/// If the start of the broadcast is known, wall-clock times are given after each discontinuity.
+pub(crate) fn media_playlist(track: &ExportTrack, meta: Option<&StreamMeta>) -> String {
+    let mut playlist = LivePlaylist::default();
+    playlist.update(track);
+    playlist.render(meta, false)
+}
+
+/// Live HLS media playlist of one track.
+///
+/// Players expect an event playlist to only grow at the end, so segments are listed in the order
+/// they were first seen rather than by time. Past segments found while downloading are published
+/// after a discontinuity following the live segments already listed.
+#[derive(Default)]
+pub(crate) struct LivePlaylist {
+    /// URIs of the segments already listed.
+    published: HashSet<String>,
+    segments: Vec<PlaylistSegment>,
+    /// The playlist was served as an event playlist, which it must stay when it ends.
+    event: bool,
+}
+
+/// Segment listed in an HLS media playlist
+#[derive(Debug)]
+struct PlaylistSegment {
+    init_uri: String,
+    uri: String,
+    /// Segment time, used for wall-clock times.
+    time: usize,
+    /// End PTS.
+    end: usize,
+    /// Duration in seconds.
+    duration: f64,
+    /// The previous segment in the playlist doesn't end where this one starts.
+    discontinuity: bool,
+}
+
+impl LivePlaylist {
+    /// Append the segments of `track` that aren't listed yet.
+    /// Segments after the end of the playlist come first, then earlier ones found since.
+    pub(crate) fn update(&mut self, track: &ExportTrack) {
+        let timescale = track.info.timescale as f64;
+        let playlist_end = self.segments.last().map_or(0, |s| s.end);
+        let mut new: Vec<_> = (0..track.timeline.len())
+            .filter(|&i| !self.published.contains(&track.uris[i]))
+            .collect();
+        new.sort_by_key(|&i| (track.timeline[i].t < playlist_end, track.timeline[i].t));
+
+        for i in new {
+            let segment = &track.timeline[i];
+            let discontinuity = self
+                .segments
+                .last()
+                .is_some_and(|last| last.end != segment.t || last.init_uri != track.init_uri);
+            self.published.insert(track.uris[i].clone());
+            self.segments.push(PlaylistSegment {
+                init_uri: track.init_uri.clone(),
+                uri: track.uris[i].clone(),
+                time: track.times[i],
+                end: segment.t + segment.d,
+                duration: segment.d as f64 / timescale,
+                discontinuity,
+            });
+        }
+    }
+
+    /// Playlist text, left open for players to reload if `live`.
+    /// If the start of the broadcast is known, wall-clock times are given after each discontinuity.
+    pub(crate) fn render(&mut self, meta: Option<&StreamMeta>, live: bool) -> String {
+        self.event |= live;
+        let target_duration = self
+            .segments
+            .iter()
+            .map(|s| s.duration.ceil() as usize)
+            .max()
+            .unwrap_or(1);
+
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+        playlist.push_str(&format!("#EXT-X-TARGETDURATION:{target_duration}\n"));
+        playlist.push_str("#EXT-X-MEDIA-SEQUENCE:0\n");
+        if self.event {
+            playlist.push_str("#EXT-X-PLAYLIST-TYPE:EVENT\n");
+        } else {
+            playlist.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
+        }
+        let mut init_uri = None;
+        for (i, segment) in self.segments.iter().enumerate() {
+            if segment.discontinuity {
+                playlist.push_str("#EXT-X-DISCONTINUITY\n");
+            }
+            if init_uri != Some(&segment.init_uri) {
+                playlist.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", segment.init_uri));
+                init_uri = Some(&segment.init_uri);
+            }
+            if i == 0 || segment.discontinuity {
+                let wall_clock = meta.and_then(|m| {
+                    let t = segment.time as f64 - m.publish_frame_time as f64;
+                    DateTime::from_timestamp_millis((m.broadcast_start * 1000.0 + t) as i64)
+                });
+                if let Some(time) = wall_clock {
+                    playlist.push_str(&format!(
+                        "#EXT-X-PROGRAM-DATE-TIME:{}\n",
+                        time.to_rfc3339_opts(SecondsFormat::Millis, true)
+                    ));
+                }
+            }
+            playlist.push_str(&format!(
+                "#EXTINF:{:.3},\n{}\n",
+                segment.duration, segment.uri
+            ));
+        }
+        if !live {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+        playlist
+    }
+}
+
+/// HLS master playlist referencing the video playlist with an audio rendition, or only the audio
+/// playlist if there is no video
+pub(crate) fn master_playlist(tracks: &[ExportTrack]) -> String {
+    let video = tracks.iter().find(|t| t.media_type == MediaType::Video);
+    let audio = tracks.iter().find(|t| t.media_type == MediaType::Audio);
+
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    if let (Some(_), Some(audio)) = (video, audio) {
+        playlist.push_str(&format!(
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"{}\",DEFAULT=YES,AUTOSELECT=YES,URI=\"{}\"\n",
+            audio.representation,
+            playlist_name(&audio.media_type)
+        ));
+    }
+
+    let main = video.or(audio).unwrap();
+    let codecs: Vec<_> = [video, audio]
+        .into_iter()
+        .flatten()
+        .map(|t| t.info.codec.as_str())
+        .collect();
+    let mut stream = format!(
+        "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},CODECS=\"{}\"",
+        tracks.iter().map(|t| t.peak_bandwidth).sum::<u64>(),
+        tracks.iter().map(|t| t.average_bandwidth).sum::<u64>(),
+        codecs.join(",")
+    );
+    if let (Some(w), Some(h)) = (main.info.width, main.info.height) {
+        stream.push_str(&format!(",RESOLUTION={w}x{h}"));
+    }
+    if video.is_some() && audio.is_some() {
+        stream.push_str(",AUDIO=\"audio\"");
+    }
+    playlist.push_str(&format!("{stream}\n{}\n", playlist_name(&main.media_type)));
+    playlist
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timeline_with_gap() {
+        let spans = [
+            (0, 90000),
+            (90000, 180000),
+            (180000, 270000),
+            (450000, 540000),
+        ];
+        let timeline = timeline(&spans);
+        assert_eq!(timeline[3].t, 450000);
+        assert!(timeline[3].discontinuity);
+        assert!(!timeline[1].discontinuity);
+        assert_eq!(
+            segment_timeline(&timeline),
+            [
+                "<S t=\"0\" d=\"90000\" r=\"2\"/>",
+                "<S t=\"450000\" d=\"90000\"/>"
+            ]
+        );
+
+        let track = ExportTrack {
+            media_type: MediaType::Video,
+            representation: "video-1".to_owned(),
+            info: InitInfo {
+                timescale: 90000,
+                codec: "avc1.64001f".to_owned(),
+                width: Some(720),
+                height: Some(1280),
+            },
+            init_uri: "segments/video-1-init.mp4".to_owned(),
+            times: vec![0, 1000, 2000, 5000],
+            uris: (0..4)
+                .map(|i| format!("segments/video-1-{i}.m4v"))
+                .collect(),
+            timeline,
+            peak_bandwidth: 0,
+            average_bandwidth: 0,
+        };
+        let meta = StreamMeta {
+            id: "123".to_owned(),
+            publish_frame_time: 0,
+            broadcast_start: 1688212800.0,
+        };
+        let playlist = media_playlist(&track, Some(&meta));
+        assert!(playlist.contains(
+            "#EXT-X-DISCONTINUITY\n#EXT-X-PROGRAM-DATE-TIME:2023-07-01T12:00:05.000Z\n\
+             #EXTINF:1.000,\nsegments/video-1-3.m4v\n"
+        ));
+        assert_eq!(playlist.matches("#EXT-X-DISCONTINUITY").count(), 1);
+    }
+
+    fn video_track(spans: &[(usize, usize)]) -> ExportTrack {
+        ExportTrack {
+            media_type: MediaType::Video,
+            representation: "video-1".to_owned(),
+            info: InitInfo {
+                timescale: 1000,
+                codec: "avc1.64001f".to_owned(),
+                width: None,
+                height: None,
+            },
+            init_uri: "segments/video-1-init.mp4".to_owned(),
+            times: spans.iter().map(|s| s.0).collect(),
+            uris: spans
+                .iter()
+                .map(|s| format!("segments/video-1-{}.m4v", s.0))
+                .collect(),
+            timeline: timeline(spans),
+            peak_bandwidth: 0,
+            average_bandwidth: 0,
+        }
+    }
+
+    #[test]
+    fn live_playlist() {
+        let mut playlist = LivePlaylist::default();
+        playlist.update(&video_track(&[(10000, 12000), (12000, 14000)]));
+        let first = playlist.render(None, true);
+        assert!(first.contains("#EXT-X-PLAYLIST-TYPE:EVENT\n"));
+        assert!(!first.contains("#EXT-X-ENDLIST"));
+
+        // A new live segment and two past segments found since the last request
+        let track = video_track(&[
+            (6000, 8000),
+            (8000, 10000),
+            (10000, 12000),
+            (12000, 14000),
+            (14000, 16000),
+        ]);
+        playlist.update(&track);
+        let second = playlist.render(None, true);
+        assert_eq!(
+            second.strip_prefix(first.as_str()),
+            Some(
+                "#EXTINF:2.000,\nsegments/video-1-14000.m4v\n\
+                 #EXT-X-DISCONTINUITY\n#EXTINF:2.000,\nsegments/video-1-6000.m4v\n\
+                 #EXTINF:2.000,\nsegments/video-1-8000.m4v\n"
+            )
+        );
+
+        // Still an event playlist after the download finished
+        playlist.update(&track);
+        let last = playlist.render(None, false);
+        assert_eq!(last, second + "#EXT-X-ENDLIST\n");
+        assert!(media_playlist(&track, None).contains("#EXT-X-PLAYLIST-TYPE:VOD\n"));
+
+        let start = DateTime::from_timestamp(1688212800, 0).unwrap();
+        let dynamic = mpd(&[track], Some(start));
+        assert!(
+            dynamic.contains("type=\"dynamic\" availabilityStartTime=\"2023-07-01T12:00:00.000Z\"")
+        );
+        assert!(dynamic.contains("presentationTimeOffset=\"0\""));
+        assert!(!dynamic.contains("mediaPresentationDuration"));
+    }
+}
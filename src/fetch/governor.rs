@@ -0,0 +1,168 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
+
+use super::FetchConfig;
+
+/// Pause after the first rate limited response without a `Retry-After` header, doubled for each
+/// one after it
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Most the request rate is divided by after rate limited responses
+const MAX_SLOWDOWN: f64 = 32.0;
+
+/// Time without rate limited responses before the request rate is doubled again
+const RECOVERY: Duration = Duration::from_secs(60);
+
+/// Spaces out requests to stay under a rate, backing off when the server asks to
+#[derive(Debug)]
+pub(crate) struct Governor {
+    /// Time between requests at the configured rate.
+    interval: Option<Duration>,
+    /// Number of requests allowed at once after being idle.
+    burst: u32,
+    /// Longest random delay added before each request.
+    jitter: Option<Duration>,
+    state: Mutex<GovernorState>,
+}
+
+#[derive(Debug)]
+struct GovernorState {
+    /// When the next request would be sent if requests were sent evenly.
+    next: Instant,
+    /// No requests are sent before this.
+    paused_until: Instant,
+    /// Factor the interval is multiplied by.
+    slowdown: f64,
+    /// Last time the server limited requests or the slowdown was reduced.
+    throttled_at: Instant,
+    backoff: Duration,
+}
+
+impl Governor {
+    pub(crate) fn new(config: &FetchConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            interval: config
+                .rate
+                .filter(|&r| r > 0.0)
+                .map(|r| Duration::from_secs_f64(1.0 / r)),
+            burst: config.burst.unwrap_or(1).max(1),
+            jitter: config.jitter,
+            state: Mutex::new(GovernorState {
+                next: now,
+                paused_until: now,
+                slowdown: 1.0,
+                throttled_at: now,
+                backoff: INITIAL_BACKOFF,
+            }),
+        }
+    }
+
+    /// Wait until the next request may be sent
+    pub(crate) async fn acquire(&self) {
+        let deadline = {
+            let mut state = self.state.lock().await;
+            let now = Instant::now();
+            let mut at = now.max(state.paused_until);
+            if let Some(interval) = self.interval {
+                // Requests may be sent early as long as no more than the burst are ahead
+                let interval = interval.mul_f64(state.slowdown);
+                let next = state.next.max(at);
+                let ahead = interval * (self.burst - 1);
+                at = at.max(next.checked_sub(ahead).unwrap_or(now));
+                state.next = next + interval;
+            }
+            at
+        };
+        let jitter = self.jitter.map_or(Duration::ZERO, |j| j.mul_f64(random()));
+        time::sleep_until(deadline + jitter).await;
+    }
+
+    /// Pause all requests after a rate limited response, and slow down afterwards.
+    /// Returns how long requests are paused for.
+    pub(crate) async fn throttled(&self, headers: &HeaderMap) -> Duration {
+        let mut state = self.state.lock().await;
+        let pause = match retry_after(headers) {
+            Some(pause) => pause,
+            None => {
+                let pause = state.backoff;
+                state.backoff = (state.backoff * 2).min(MAX_BACKOFF);
+                pause
+            }
+        };
+        let now = Instant::now();
+        state.paused_until = state.paused_until.max(now + pause);
+        state.slowdown = (state.slowdown * 2.0).min(MAX_SLOWDOWN);
+        state.throttled_at = now;
+        pause
+    }
+
+    /// Gradually return to the configured rate while requests aren't limited
+    pub(crate) async fn succeeded(&self) {
+        let mut state = self.state.lock().await;
+        if state.slowdown > 1.0 && state.throttled_at.elapsed() >= RECOVERY {
+            state.slowdown = (state.slowdown / 2.0).max(1.0);
+            state.throttled_at = Instant::now();
+            if state.slowdown == 1.0 {
+                state.backoff = INITIAL_BACKOFF;
+            }
+        }
+    }
+}
+
+/// Delay requested by a `Retry-After` header in seconds
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds).min(MAX_BACKOFF))
+}
+
+/// Random number in `[0, 1)`
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_and_burst() {
+        let governor = Governor::new(&FetchConfig {
+            rate: Some(2.0),
+            burst: Some(3),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            governor.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        governor.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        // Rate limited responses pause, then halve the rate
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "10".parse().unwrap());
+        assert_eq!(governor.throttled(&headers).await, Duration::from_secs(10));
+        let start = Instant::now();
+        governor.acquire().await;
+        governor.acquire().await;
+        governor.acquire().await;
+        governor.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(11));
+    }
+}
@@ -0,0 +1,206 @@
+mod governor;
+mod traffic;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode, Url};
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{self, Instant};
+
+use self::governor::Governor;
+use self::traffic::{TrafficRecorder, TrafficReplay};
+
+/// Number of times a rate limited request is sent again
+const MAX_RETRIES: usize = 5;
+
+/// Limits shared by every request made through a [Fetcher]
+#[derive(Clone, Debug, Default)]
+pub struct FetchConfig {
+    /// Maximum number of requests in flight at once.
+    /// If `None`, requests are not limited.
+    pub max_requests: Option<usize>,
+
+    /// Maximum average download rate in bytes per second.
+    /// If `None`, bandwidth is not limited.
+    pub max_bandwidth: Option<u64>,
+
+    /// Save every request and response to a traffic archive in this directory, to reproduce the
+    /// download later with `replay_traffic`.
+    pub record_traffic: Option<PathBuf>,
+
+    /// Answer requests from a traffic archive in this directory instead of the network.
+    /// Each URL gets its recorded responses in order with their recorded latency, URLs that were
+    /// never requested are not found.
+    pub replay_traffic: Option<PathBuf>,
+
+    /// Maximum average number of past segment probes per second.
+    /// If `None`, probes are not spaced out. The rate is lowered automatically when the server
+    /// responds to a probe with 429 Too Many Requests or `Retry-After`, either way.
+    /// Manifest and live segment requests are never delayed.
+    pub rate: Option<f64>,
+
+    /// Number of probes that may be sent at once after being idle, at most `rate` on average.
+    /// If `None`, 1.
+    pub burst: Option<u32>,
+
+    /// Add a random delay up to this long before each probe.
+    pub jitter: Option<Duration>,
+
+    /// Maximum number of segment times each download tries while searching for past segments.
+    /// If `None`, search until the start of the stream.
+    pub max_probes: Option<usize>,
+}
+
+/// HTTP client shared between downloads.
+///
+/// Cloning a `Fetcher` is cheap, all clones share the same connection pool and limits.
+#[derive(Clone, Debug)]
+pub struct Fetcher {
+    client: Client,
+    requests: Option<Arc<Semaphore>>,
+    bandwidth: Option<Arc<Bandwidth>>,
+    recorder: Option<Arc<TrafficRecorder>>,
+    replay: Option<Arc<TrafficReplay>>,
+    governor: Arc<Governor>,
+    max_probes: Option<usize>,
+}
+
+/// Fully received HTTP response
+pub(crate) struct Fetched {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+#[derive(Debug)]
+struct Bandwidth {
+    bytes_per_sec: u64,
+    next: Mutex<Instant>,
+}
+
+impl Fetcher {
+    /// Create a new fetcher with the given limits.
+    pub fn new(config: &FetchConfig) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(5)).build()?;
+        Ok(Self {
+            client,
+            requests: config
+                .max_requests
+                .map(|n| Arc::new(Semaphore::new(n.max(1)))),
+            bandwidth: config.max_bandwidth.map(|b| {
+                Arc::new(Bandwidth {
+                    bytes_per_sec: b.max(1),
+                    next: Mutex::new(Instant::now()),
+                })
+            }),
+            recorder: match &config.record_traffic {
+                Some(dir) => Some(Arc::new(TrafficRecorder::create(dir)?)),
+                None => None,
+            },
+            replay: match &config.replay_traffic {
+                Some(dir) => Some(Arc::new(TrafficReplay::open(dir)?)),
+                None => None,
+            },
+            governor: Arc::new(Governor::new(config)),
+            max_probes: config.max_probes,
+        })
+    }
+
+    /// Maximum number of past segment probes per download
+    pub(crate) fn max_probes(&self) -> Option<usize> {
+        self.max_probes
+    }
+
+    /// Send a request that must not be delayed, like polling the manifest or downloading a live
+    /// segment before it leaves the manifest
+    pub(crate) async fn get(&self, url: &Url) -> Result<Fetched> {
+        if let Some(replay) = &self.replay {
+            return replay.get(url).await;
+        }
+
+        let sent = Instant::now();
+        let result = self.fetch(url).await;
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = recorder.record(url, sent, &result) {
+                eprintln!("WARNING: Failed to record traffic: {e}");
+            }
+        }
+        result
+    }
+
+    /// Send a request searching for a past segment, spaced out to stay under the configured rate
+    /// and slowed down when the server limits the rate
+    pub(crate) async fn probe(&self, url: &Url) -> Result<Fetched> {
+        if self.replay.is_some() {
+            return self.get(url).await;
+        }
+
+        let mut retries = 0;
+        loop {
+            self.governor.acquire().await;
+            let result = self.get(url).await;
+
+            // Back off when asked to, retrying rate limited requests
+            let Ok(fetched) = &result else {
+                return result;
+            };
+            let limited = fetched.status == StatusCode::TOO_MANY_REQUESTS;
+            if limited || fetched.headers.contains_key(RETRY_AFTER) {
+                let pause = self.governor.throttled(&fetched.headers).await;
+                eprintln!(
+                    "WARNING: Rate limited ({}), pausing past segment search for {}s and slowing \
+                     down",
+                    fetched.status,
+                    pause.as_secs()
+                );
+                if limited && retries < MAX_RETRIES {
+                    retries += 1;
+                    continue;
+                }
+            } else {
+                self.governor.succeeded().await;
+            }
+            return result;
+        }
+    }
+
+    async fn fetch(&self, url: &Url) -> Result<Fetched> {
+        let _permit = match &self.requests {
+            Some(s) => Some(s.acquire().await?),
+            None => None,
+        };
+
+        let resp = self.client.get(url.as_str()).send().await?;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = resp.bytes().await?;
+
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.consume(body.len()).await;
+        }
+
+        Ok(Fetched {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+impl Bandwidth {
+    /// Delay the caller so the average rate stays below the limit
+    async fn consume(&self, bytes: usize) {
+        let deadline = {
+            let mut next = self.next.lock().await;
+            let start = std::cmp::max(*next, Instant::now());
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            *next
+        };
+        time::sleep_until(deadline).await;
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tokio::time::{self, Instant};
+
+use super::Fetched;
+use crate::store::sha256_hex;
+
+/// Name of the request log in a traffic archive
+const LOG_NAME: &str = "traffic.jsonl";
+
+/// Directory of response bodies in a traffic archive
+const BODIES_DIR: &str = "bodies";
+
+/// One request and its outcome
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Exchange {
+    /// Milliseconds since recording started when the request was sent.
+    at: u64,
+
+    url: String,
+
+    /// Milliseconds until the whole response was received or the request failed.
+    latency: u64,
+
+    /// HTTP status, `None` if the request failed.
+    status: Option<u16>,
+
+    headers: Vec<(String, String)>,
+
+    /// File in the bodies directory holding the body of a successful response, named by its
+    /// SHA-256.
+    body: Option<String>,
+
+    /// Why the request failed.
+    error: Option<String>,
+}
+
+/// Saves every request made through a fetcher to a traffic archive
+#[derive(Debug)]
+pub(crate) struct TrafficRecorder {
+    dir: PathBuf,
+    log: Mutex<File>,
+    started: Instant,
+}
+
+/// Answers requests from a traffic archive instead of the network
+#[derive(Debug)]
+pub(crate) struct TrafficReplay {
+    dir: PathBuf,
+    /// Recorded exchanges of each URL in the order they happened.
+    exchanges: Mutex<HashMap<String, VecDeque<Exchange>>>,
+}
+
+impl TrafficRecorder {
+    pub(crate) fn create(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir.join(BODIES_DIR))?;
+        let log = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(dir.join(LOG_NAME))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            log: Mutex::new(log),
+            started: Instant::now(),
+        })
+    }
+
+    /// Record the result of a request to `url` sent at `sent`
+    pub(crate) fn record(&self, url: &Url, sent: Instant, result: &Result<Fetched>) -> Result<()> {
+        let mut exchange = Exchange {
+            at: sent.duration_since(self.started).as_millis() as u64,
+            url: url.to_string(),
+            latency: sent.elapsed().as_millis() as u64,
+            status: None,
+            headers: vec![],
+            body: None,
+            error: None,
+        };
+        match result {
+            Ok(fetched) => {
+                exchange.status = Some(fetched.status.as_u16());
+                exchange.headers = fetched
+                    .headers
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).into()))
+                    .collect();
+                if fetched.status.is_success() {
+                    let name = sha256_hex(&fetched.body);
+                    let path = self.dir.join(BODIES_DIR).join(&name);
+                    if !path.exists() {
+                        fs::write(path, &fetched.body)?;
+                    }
+                    exchange.body = Some(name);
+                }
+            }
+            Err(e) => exchange.error = Some(e.to_string()),
+        }
+
+        let mut line = serde_json::to_vec(&exchange)?;
+        line.push(b'\n');
+        self.log.lock().unwrap().write_all(&line)?;
+        Ok(())
+    }
+}
+
+impl TrafficReplay {
+    pub(crate) fn open(dir: &Path) -> Result<Self> {
+        let log = File::open(dir.join(LOG_NAME))
+            .map_err(|e| anyhow!("Can't open traffic archive {}: {e}", dir.display()))?;
+        let mut exchanges: HashMap<String, VecDeque<Exchange>> = HashMap::new();
+        for line in BufReader::new(log).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange: Exchange = serde_json::from_str(&line)?;
+            exchanges
+                .entry(exchange.url.clone())
+                .or_default()
+                .push_back(exchange);
+        }
+        Ok(Self {
+            dir: dir.to_owned(),
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    /// Answer with the next recorded response for `url` after its recorded latency. The last
+    /// response is repeated once all have been used, URLs never requested are not found.
+    pub(crate) async fn get(&self, url: &Url) -> Result<Fetched> {
+        let exchange = {
+            let mut exchanges = self.exchanges.lock().unwrap();
+            match exchanges.get_mut(url.as_str()) {
+                Some(queue) if queue.len() > 1 => queue.pop_front(),
+                Some(queue) => queue.front().cloned(),
+                None => None,
+            }
+        };
+        let Some(exchange) = exchange else {
+            return Ok(Fetched {
+                status: StatusCode::NOT_FOUND,
+                headers: HeaderMap::new(),
+                body: Bytes::new(),
+            });
+        };
+
+        time::sleep(Duration::from_millis(exchange.latency)).await;
+        if let Some(error) = exchange.error {
+            bail!("{error} (replayed)");
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in &exchange.headers {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        let body = match &exchange.body {
+            Some(name) => tokio::fs::read(self.dir.join(BODIES_DIR).join(name)).await?,
+            None => vec![],
+        };
+        Ok(Fetched {
+            status: StatusCode::from_u16(exchange.status.unwrap_or(404))?,
+            headers,
+            body: body.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("iglive-traffic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let manifest = Url::parse("http://localhost/manifest.mpd").unwrap();
+        let segment = Url::parse("http://localhost/1000.m4s").unwrap();
+        let response = |status, body: &'static [u8]| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-fb-video-broadcast-ended", HeaderValue::from_static("1"));
+            Ok(Fetched {
+                status,
+                headers,
+                body: Bytes::from_static(body),
+            })
+        };
+
+        let recorder = TrafficRecorder::create(&dir).unwrap();
+        let sent = Instant::now();
+        recorder
+            .record(&manifest, sent, &response(StatusCode::OK, b"first"))
+            .unwrap();
+        recorder
+            .record(&manifest, sent, &response(StatusCode::OK, b"second"))
+            .unwrap();
+        recorder
+            .record(&segment, sent, &Err(anyhow!("timed out")))
+            .unwrap();
+        drop(recorder);
+
+        let replay = TrafficReplay::open(&dir).unwrap();
+        let body = |fetched: Fetched| fetched.body.to_vec();
+        assert_eq!(body(replay.get(&manifest).await.unwrap()), b"first");
+        let second = replay.get(&manifest).await.unwrap();
+        assert!(second.headers.contains_key("x-fb-video-broadcast-ended"));
+        assert_eq!(body(second), b"second");
+        assert_eq!(body(replay.get(&manifest).await.unwrap()), b"second");
+        assert!(replay.get(&segment).await.is_err());
+        let unknown = Url::parse("http://localhost/2000.m4s").unwrap();
+        assert_eq!(
+            replay.get(&unknown).await.unwrap().status,
+            StatusCode::NOT_FOUND
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
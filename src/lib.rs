@@ -10,15 +10,44 @@
 //! However, a valid `.mpd` link must be provided, which may be impossible to get at that point if
 //! you do not have an existing link.
 
+/// Extract part of a downloaded live stream
+pub mod clip;
+
+/// Long-running download daemon with an HTTP control API
+pub mod daemon;
+
 /// IG live segment downloader
 pub mod download;
 
 mod error;
 
+/// DASH and HLS manifests for downloaded live streams
+pub mod export;
+
+/// Shared HTTP client
+pub mod fetch;
+
 /// Video and audio segment merger
 pub mod merge;
 
+mod mp4;
 mod mpd;
 
-mod state;
+/// Local HTTP server for watching downloaded live streams
+pub mod play;
+
 mod pts;
+
+/// Fake IG live server for testing downloads locally
+pub mod simulate;
+
+mod state;
+
+/// Storage backends for downloaded segments
+pub mod store;
+
+/// Integrity verification of downloaded segments
+pub mod verify;
+
+/// Download several live streams at once
+pub mod supervisor;
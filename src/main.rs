@@ -1,10 +1,29 @@
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use download_iglive::download::{download, DownloadConfig, DownloadSegments};
-use download_iglive::merge::merge;
+use anyhow::{anyhow, Result};
+use clap::builder::PossibleValuesParser;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use download_iglive::clip::{clip, ClipConfig, ClipTime};
+use download_iglive::daemon::{serve, DaemonConfig};
+use download_iglive::download::{
+    download_with, CaptureLimits, DownloadConfig, DownloadContext, DownloadSegments, PastRange,
+    PipeOutput,
+};
+use download_iglive::export::{export, ExportConfig};
+use download_iglive::fetch::{FetchConfig, Fetcher};
+use download_iglive::merge::{
+    merge, Align, GapFill, MergeConfig, OutputFormat, SplitConfig, TranscodeProfile, PROFILES,
+};
+use download_iglive::play::{play, play_download, PlayConfig};
+use download_iglive::simulate::{generate, simulate, SimulateConfig};
+use download_iglive::store::{migrate, FsStore};
+use download_iglive::supervisor::{Supervisor, SupervisorConfig};
+use download_iglive::verify::verify;
+use indicatif::MultiProgress;
+use tokio::io::{self, AsyncBufReadExt, BufReader};
 
 /// Download Instagram live streams, including past segments
 #[derive(Parser, Debug)]
@@ -16,7 +35,15 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Command {
     Download(Download),
+    Multi(Multi),
+    Serve(Serve),
     Merge(Merge),
+    Clip(Clip),
+    Export(Export),
+    Play(Play),
+    Simulate(Simulate),
+    Migrate(Migrate),
+    Verify(Verify),
 }
 
 /// Download a live stream
@@ -33,9 +60,146 @@ struct Download {
     #[clap(short, long)]
     no_merge: bool,
 
+    #[clap(flatten)]
+    merge: MergeArgs,
+
+    /// Don't download past segments
+    #[clap(short, long)]
+    live_only: bool,
+
+    /// Also write live video and audio as one fragmented MP4 stream to this file or named pipe,
+    /// or to stdout if `-` (combine with --no-merge to keep stdout clean)
+    #[clap(long)]
+    pipe: Option<PathBuf>,
+
+    /// Also push live video and audio to this RTMP or SRT URL with ffmpeg, can be repeated
+    #[clap(long)]
+    restream: Vec<String>,
+
+    /// Append live segments to recording.mp4 while downloading, so merging afterwards only adds
+    /// the past segments
+    #[clap(long, conflicts_with = "no_merge")]
+    incremental: bool,
+
+    /// Save every request and response to a traffic archive in this directory
+    #[clap(long)]
+    record_traffic: Option<PathBuf>,
+
+    /// Download from a traffic archive saved with --record-traffic instead of the network,
+    /// the .mpd URL must be the one recorded
+    #[clap(long, conflicts_with = "record_traffic")]
+    replay_traffic: Option<PathBuf>,
+
+    /// Log every segment time tried while searching for past segments to this JSONL file
+    #[clap(long)]
+    probe_log: Option<PathBuf>,
+
+    /// Only download past segments from this long before joining, as [[HH:]MM:]SS
+    #[clap(long, value_parser = parse_past, conflicts_with = "live_only")]
+    past: Option<PastRange>,
+
+    /// Only download past segments from this time on, relative to the start of the broadcast,
+    /// as a wall-clock time, or as a segment time `t=<N>`
+    #[clap(long, value_parser = parse_past_since, conflicts_with_all = ["live_only", "past"])]
+    past_since: Option<PastRange>,
+
+    #[clap(flatten)]
+    rate: RateArgs,
+
+    #[clap(flatten)]
+    limits: LimitArgs,
+}
+
+fn parse_past(s: &str) -> Result<PastRange> {
+    match s.parse()? {
+        ClipTime::Relative(seconds) => Ok(PastRange::Last(seconds)),
+        ClipTime::WallClock(_) => Err(anyhow!("Expected a duration: {s}")),
+    }
+}
+
+fn parse_past_since(s: &str) -> Result<PastRange> {
+    if let Some(t) = s.strip_prefix("t=") {
+        return Ok(PastRange::FromTime(t.parse()?));
+    }
+    Ok(match s.parse()? {
+        ClipTime::Relative(seconds) => PastRange::FromStart(seconds),
+        ClipTime::WallClock(unix) => PastRange::FromWallClock(unix),
+    })
+}
+
+/// Download several live streams at once
+#[derive(Parser, Debug)]
+struct Multi {
+    /// URLs of .mpd files
+    mpd_urls: Vec<String>,
+
+    /// File containing .mpd URLs, one per line
+    #[clap(short, long)]
+    file: Option<PathBuf>,
+
+    /// Read more .mpd URLs from stdin while downloading, one per line
+    #[clap(long)]
+    stdin: bool,
+
+    /// Directory to place each stream's directory in
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Don't merge into one video file after download
+    #[clap(short, long)]
+    no_merge: bool,
+
+    #[clap(flatten)]
+    merge: MergeArgs,
+
+    /// Don't download past segments
+    #[clap(short, long)]
+    live_only: bool,
+
+    /// Maximum number of requests in flight across all streams
+    #[clap(long)]
+    max_requests: Option<usize>,
+
+    /// Maximum total download rate in KiB/s across all streams
+    #[clap(long)]
+    max_bandwidth: Option<u64>,
+
+    #[clap(flatten)]
+    rate: RateArgs,
+
+    #[clap(flatten)]
+    limits: LimitArgs,
+}
+
+/// Run a download daemon controlled through a local HTTP/JSON API
+#[derive(Parser, Debug)]
+struct Serve {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Directory to keep the job list and event logs in
+    #[clap(long, default_value = ".iglive-daemon")]
+    state_dir: PathBuf,
+
+    /// Directory to place each stream's directory in
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
     /// Don't download past segments
     #[clap(short, long)]
     live_only: bool,
+
+    /// Maximum number of requests in flight across all streams
+    #[clap(long)]
+    max_requests: Option<usize>,
+
+    /// Maximum total download rate in KiB/s across all streams
+    #[clap(long)]
+    max_bandwidth: Option<u64>,
+
+    #[clap(flatten)]
+    rate: RateArgs,
 }
 
 /// Merge an already downloaded live stream into one file
@@ -43,12 +207,366 @@ struct Download {
 struct Merge {
     /// Directory to merge
     directory: PathBuf,
+
+    #[clap(flatten)]
+    merge: MergeArgs,
+}
+
+/// Merge part of an already downloaded live stream into one file
+#[derive(Parser, Debug)]
+struct Clip {
+    /// Directory to clip
+    directory: PathBuf,
+
+    /// Start of the clip, as [[HH:]MM:]SS from the start of the broadcast, or a date and time
+    /// (RFC 3339, or YYYY-MM-DD HH:MM:SS in local time)
+    #[clap(long)]
+    from: Option<ClipTime>,
+
+    /// End of the clip, in the same formats as --from
+    #[clap(long)]
+    to: Option<ClipTime>,
+
+    /// Cut at the nearest packets without re-encoding instead of at exact frames
+    #[clap(long)]
+    fast: bool,
+
+    #[clap(flatten)]
+    merge: MergeArgs,
+}
+
+/// Write DASH and HLS manifests to play an already downloaded live stream without merging
+#[derive(Parser, Debug)]
+struct Export {
+    /// Directory to export
+    directory: PathBuf,
+
+    /// Only write a DASH manifest
+    #[clap(long, conflicts_with = "hls")]
+    dash: bool,
+
+    /// Only write HLS playlists
+    #[clap(long)]
+    hls: bool,
+
+    /// URL prefix for the segments, if they are served from somewhere other than the manifests
+    #[clap(long)]
+    base_url: Option<String>,
+}
+
+/// Serve a live stream over HTTP as DASH and HLS to watch it in a player, downloading it first if
+/// given a URL
+#[derive(Parser, Debug)]
+struct Play {
+    /// URL of .mpd file to download while serving, or directory of an already downloaded stream
+    input: String,
+
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8000")]
+    listen: SocketAddr,
+
+    /// Output directory when downloading
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Don't download past segments
+    #[clap(short, long)]
+    live_only: bool,
+}
+
+/// Serve a downloaded live stream as a fake IG live stream, to test downloading it
+#[derive(Parser, Debug)]
+struct Simulate {
+    /// Directory of a downloaded stream
+    directory: PathBuf,
+
+    /// Generate test media of this many seconds with ffmpeg into the directory first
+    #[clap(long)]
+    generate: Option<u32>,
+
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Seconds of the stream already broadcast when starting, only available as past segments
+    #[clap(long, default_value_t = 60.0)]
+    position: f64,
+
+    /// How fast new segments are published, 1 is real time
+    #[clap(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Number of latest segments listed in the manifest
+    #[clap(long, default_value_t = 5)]
+    window: usize,
+}
+
+/// Options for limiting the request rate
+#[derive(ClapArgs, Debug)]
+struct RateArgs {
+    /// Maximum average number of past segment probes per second, lowered automatically when the
+    /// server responds with 429 Too Many Requests
+    #[clap(long)]
+    rate: Option<f64>,
+
+    /// Number of probes that may be sent at once after being idle
+    #[clap(long, requires = "rate")]
+    burst: Option<u32>,
+
+    /// Add a random delay of up to this many milliseconds before each probe
+    #[clap(long)]
+    jitter: Option<u64>,
+
+    /// Maximum number of segment times to try per stream while searching for past segments
+    #[clap(long)]
+    max_probes: Option<usize>,
+}
+
+impl From<RateArgs> for FetchConfig {
+    fn from(args: RateArgs) -> Self {
+        Self {
+            rate: args.rate,
+            burst: args.burst,
+            jitter: args.jitter.map(Duration::from_millis),
+            max_probes: args.max_probes,
+            ..Default::default()
+        }
+    }
+}
+
+/// Options for when to start and stop downloading
+#[derive(ClapArgs, Debug)]
+struct LimitArgs {
+    /// Wait until this wall-clock time, or for this long as [[HH:]MM:]SS, before starting
+    #[clap(long)]
+    start_at: Option<ClipTime>,
+
+    /// Stop downloading live segments after this long, as [[HH:]MM:]SS
+    #[clap(long)]
+    max_duration: Option<ClipTime>,
+
+    /// Stop downloading live segments at this wall-clock time, or after this long as
+    /// [[HH:]MM:]SS
+    #[clap(long)]
+    until: Option<ClipTime>,
+
+    /// Stop downloading live and past segments once the download directory is larger than this
+    /// many MiB
+    #[clap(long)]
+    max_dir_size: Option<u64>,
+}
+
+impl TryFrom<LimitArgs> for CaptureLimits {
+    type Error = anyhow::Error;
+
+    fn try_from(args: LimitArgs) -> Result<Self> {
+        let time = |t| match t {
+            ClipTime::Relative(seconds) => SystemTime::now() + Duration::from_secs_f64(seconds),
+            ClipTime::WallClock(unix) => UNIX_EPOCH + Duration::from_secs_f64(unix),
+        };
+        let max_duration = match args.max_duration {
+            Some(ClipTime::Relative(seconds)) => Some(Duration::from_secs_f64(seconds)),
+            Some(ClipTime::WallClock(_)) => {
+                return Err(anyhow!(
+                    "--max-duration must be a duration, use --until instead"
+                ))
+            }
+            None => None,
+        };
+        Ok(Self {
+            start_at: args.start_at.map(time),
+            max_duration,
+            deadline: args.until.map(time),
+            max_size: args.max_dir_size.map(|s| s * 1024 * 1024),
+        })
+    }
+}
+
+/// Options for merging into one video file
+#[derive(ClapArgs, Debug)]
+struct MergeArgs {
+    /// Fill missing segments with generated video and silence instead of joining across gaps
+    #[clap(long, value_enum)]
+    fill_gaps: Option<FillGaps>,
+
+    /// Cut video and audio to the span covered by both, or keep both whole
+    #[clap(long, value_enum, default_value = "trim")]
+    align: AlignTracks,
+
+    /// Container of the merged file
+    #[clap(long, value_enum, default_value = "mp4")]
+    format: Format,
+
+    /// Directory to write the merged file to, defaults to the download directory
+    #[clap(long)]
+    merge_dir: Option<PathBuf>,
+
+    /// Merged file name, `{name}` is replaced with the download directory name and `{ext}` with
+    /// the format's extension [default: {name}.{ext}, or {name}-clip.{ext} for clips]
+    #[clap(long)]
+    name: Option<String>,
+
+    /// Re-encode the merged file with a transcode profile instead of copying
+    #[clap(long, value_parser = PossibleValuesParser::new(PROFILES.map(|(name, _)| name)))]
+    transcode: Option<String>,
+
+    /// Override the profile's constant rate factor
+    #[clap(long, requires = "transcode")]
+    crf: Option<u8>,
+
+    /// Override the profile's audio bitrate in kbit/s
+    #[clap(long, requires = "transcode")]
+    audio_bitrate: Option<u32>,
+
+    /// Scale video down so its longer side is at most this many pixels
+    #[clap(long, requires = "transcode")]
+    max_size: Option<u32>,
+
+    /// Encode video in two passes with this bitrate in kbit/s instead of a constant rate factor
+    #[clap(long, requires = "transcode")]
+    two_pass: Option<u32>,
+
+    /// Split the merged file into parts of at most this many seconds
+    #[clap(long)]
+    part_duration: Option<f64>,
+
+    /// Split the merged file into parts of at most this many MiB, before any transcoding
+    #[clap(long)]
+    part_size: Option<u64>,
+
+    /// Write an .m3u8 playlist listing the parts when splitting
+    #[clap(long)]
+    playlist: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FillGaps {
+    /// Black frames
+    Black,
+    /// Repeat the last frame before the gap
+    Freeze,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum AlignTracks {
+    /// Cut to the span covered by both tracks
+    Trim,
+    /// Keep both tracks whole
+    Pad,
+}
+
+impl From<AlignTracks> for Align {
+    fn from(align: AlignTracks) -> Self {
+        match align {
+            AlignTracks::Trim => Self::Trim,
+            AlignTracks::Pad => Self::Pad,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// MP4
+    Mp4,
+    /// Fragmented MP4
+    Fmp4,
+    /// Matroska
+    Mkv,
+    /// MPEG transport stream
+    Ts,
+    /// Audio-only MP4
+    M4a,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Mp4 => Self::Mp4,
+            Format::Fmp4 => Self::Fmp4,
+            Format::Mkv => Self::Mkv,
+            Format::Ts => Self::Ts,
+            Format::M4a => Self::M4a,
+        }
+    }
+}
+
+impl From<MergeArgs> for MergeConfig {
+    fn from(args: MergeArgs) -> Self {
+        Self {
+            fill_gaps: args.fill_gaps.map(|f| match f {
+                FillGaps::Black => GapFill::Black,
+                FillGaps::Freeze => GapFill::Freeze,
+            }),
+            align: args.align.into(),
+            format: args.format.into(),
+            output_dir: args.merge_dir,
+            output_name: args.name,
+            transcode: args
+                .transcode
+                .as_deref()
+                .and_then(TranscodeProfile::named)
+                .map(|p| TranscodeProfile {
+                    crf: args.crf.unwrap_or(p.crf),
+                    audio_bitrate: args.audio_bitrate.unwrap_or(p.audio_bitrate),
+                    max_size: args.max_size.or(p.max_size),
+                    video_bitrate: args.two_pass.or(p.video_bitrate),
+                    two_pass: args.two_pass.is_some() || p.two_pass,
+                    ..p
+                }),
+            split: (args.part_duration.is_some() || args.part_size.is_some()).then(|| {
+                SplitConfig {
+                    max_duration: args.part_duration,
+                    max_size: args.part_size.map(|s| s * 1024 * 1024),
+                    playlist: args.playlist,
+                }
+            }),
+        }
+    }
+}
+
+/// Convert a directory downloaded by an older version to store initialization data only once
+#[derive(Parser, Debug)]
+struct Migrate {
+    /// Directory to convert
+    directory: PathBuf,
+}
+
+/// Check that a downloaded live stream is intact
+#[derive(Parser, Debug)]
+struct Verify {
+    /// Directory to check
+    directory: PathBuf,
+
+    /// Merged file to check, defaults to the file merge writes with the same --format,
+    /// --merge-dir and --name
+    #[clap(short, long)]
+    merged: Option<PathBuf>,
+
+    /// Container of the merged file
+    #[clap(long, value_enum, default_value = "mp4")]
+    format: Format,
+
+    /// Directory the merged file was written to, defaults to the download directory
+    #[clap(long)]
+    merge_dir: Option<PathBuf>,
+
+    /// Merged file name template [default: {name}.{ext}]
+    #[clap(long)]
+    name: Option<String>,
+
+    /// How video and audio were lined up when merging
+    #[clap(long, value_enum, default_value = "trim")]
+    align: AlignTracks,
+
+    /// Print the report as JSON
+    #[clap(long)]
+    json: bool,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    if let Err(e) =  run(args).await {
+    if let Err(e) = run(args).await {
         eprintln!("{e}");
         process::exit(1);
     }
@@ -65,19 +583,229 @@ async fn run(args: Args) -> Result<()> {
             };
             let config = DownloadConfig {
                 dir: d.output,
+                parent_dir: None,
                 segments,
+                store: None,
+                events: None,
+                pipes: d
+                    .pipe
+                    .map(|p| match p.to_str() {
+                        Some("-") => PipeOutput::Stdout,
+                        _ => PipeOutput::Path(p),
+                    })
+                    .into_iter()
+                    .chain(d.restream.into_iter().map(PipeOutput::Restream))
+                    .collect(),
+                incremental: d.incremental,
+                probe_log: d.probe_log,
+                past_range: d.past.or(d.past_since),
+                limits: d.limits.try_into()?,
+            };
+
+            let ctx = DownloadContext {
+                fetcher: Fetcher::new(&FetchConfig {
+                    record_traffic: d.record_traffic,
+                    replay_traffic: d.replay_traffic,
+                    ..d.rate.into()
+                })?,
+                progress: MultiProgress::new(),
+                stream_headers: false,
             };
 
             // Download live stream
-            let output_dir = download(&d.mpd_url, config).await?;
+            let output_dir = download_with(&ctx, &d.mpd_url, config).await?;
 
             // Merge
             if !d.no_merge {
-                merge(output_dir).await?;
+                merge(output_dir, &d.merge.into()).await?;
+            }
+        }
+        Command::Multi(m) => run_multi(m).await?,
+        Command::Serve(s) => {
+            let segments = if s.live_only {
+                DownloadSegments::LIVE
+            } else {
+                DownloadSegments::all()
+            };
+            let config = DaemonConfig {
+                addr: s.listen,
+                state_dir: s.state_dir,
+                output: s.output,
+                fetch: FetchConfig {
+                    max_requests: s.max_requests,
+                    max_bandwidth: s.max_bandwidth.map(|b| b * 1024),
+                    ..s.rate.into()
+                },
+                segments,
+            };
+            serve(config).await?;
+        }
+        Command::Merge(m) => {
+            merge(m.directory, &m.merge.into()).await?;
+        }
+        Command::Clip(c) => {
+            let config = ClipConfig {
+                from: c.from,
+                to: c.to,
+                precise: !c.fast,
+                merge: c.merge.into(),
+            };
+            clip(c.directory, &config).await?;
+        }
+        Command::Export(e) => {
+            let config = ExportConfig {
+                dash: !e.hls,
+                hls: !e.dash,
+                base_url: e.base_url,
+            };
+            let report = export(e.directory, &config).await?;
+            if report.discontinuities > 0 {
+                println!("Marked {} discontinuities", report.discontinuities);
+            }
+        }
+        Command::Play(p) => {
+            let config = PlayConfig { addr: p.listen };
+            if p.input.starts_with("http://") || p.input.starts_with("https://") {
+                let segments = if p.live_only {
+                    DownloadSegments::LIVE
+                } else {
+                    DownloadSegments::all()
+                };
+                let download = DownloadConfig {
+                    dir: p.output,
+                    parent_dir: None,
+                    segments,
+                    store: None,
+                    events: None,
+                    pipes: vec![],
+                    incremental: false,
+                    probe_log: None,
+                    past_range: None,
+                    limits: Default::default(),
+                };
+                play_download(&p.input, download, &config).await?;
+            } else {
+                play(&p.input, &config).await?;
+            }
+        }
+        Command::Simulate(s) => {
+            if let Some(duration) = s.generate {
+                generate(&s.directory, duration).await?;
+            }
+            let config = SimulateConfig {
+                addr: s.listen,
+                position: s.position,
+                speed: s.speed,
+                window: s.window,
+            };
+            simulate(s.directory, &config).await?;
+        }
+        Command::Migrate(m) => {
+            let count = migrate(&FsStore::new(m.directory)).await?;
+            println!("Converted {count} segments");
+        }
+        Command::Verify(v) => {
+            let config = MergeConfig {
+                align: v.align.into(),
+                format: v.format.into(),
+                output_dir: v.merge_dir,
+                output_name: v.name,
+                ..Default::default()
+            };
+            let report = verify(v.directory, v.merged, &config).await?;
+            if v.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("Checked {} segments", report.segments);
+                if !report.indexed {
+                    println!("No index, hashes and PTS were not checked");
+                }
+                for (media_type, duration) in &report.durations {
+                    println!("{media_type}: {duration:.1}s");
+                }
+                if let Some(d) = report.merged_duration {
+                    println!("merged: {d:.1}s");
+                }
+                for problem in &report.problems {
+                    println!("{problem}");
+                }
+            }
+            if !report.problems.is_empty() {
+                anyhow::bail!("Found {} problems", report.problems.len());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_multi(m: Multi) -> Result<()> {
+    // Config
+    let segments = if m.live_only {
+        DownloadSegments::LIVE
+    } else {
+        DownloadSegments::all()
+    };
+    let config = SupervisorConfig {
+        fetch: FetchConfig {
+            max_requests: m.max_requests,
+            max_bandwidth: m.max_bandwidth.map(|b| b * 1024),
+            ..m.rate.into()
+        },
+        output: m.output,
+        segments,
+        merge: (!m.no_merge).then(|| m.merge.into()),
+        limits: m.limits.try_into()?,
+    };
+    let mut supervisor = Supervisor::new(config)?;
+
+    // Add streams
+    let mut urls = m.mpd_urls;
+    if let Some(f) = m.file {
+        urls.extend(
+            tokio::fs::read_to_string(f)
+                .await?
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(str::to_owned),
+        );
+    }
+    for url in urls {
+        supervisor.add(url);
+    }
+
+    // Wait for streams to finish, accepting new ones from stdin
+    let mut stdin = m.stdin.then(|| BufReader::new(io::stdin()).lines());
+    let mut failed = 0;
+    loop {
+        if supervisor.active() == 0 && stdin.is_none() {
+            break;
+        }
+        tokio::select! {
+            Some(job) = supervisor.join_next() => match job.result {
+                Ok(dir) => supervisor.println(format!("Finished {} ({})", job.mpd_url, dir.display())),
+                Err(e) => {
+                    failed += 1;
+                    supervisor.println(format!("Failed {}: {e}", job.mpd_url));
+                }
+            },
+            line = async { stdin.as_mut().unwrap().next_line().await }, if stdin.is_some() => {
+                match line? {
+                    Some(url) if !url.trim().is_empty() => {
+                        if !supervisor.add(url.trim()) {
+                            supervisor.println(format!("Already downloading {}", url.trim()));
+                        }
+                    }
+                    Some(_) => {}
+                    None => stdin = None,
+                }
             }
         }
-        Command::Merge(m) => merge(m.directory).await?,
     }
 
+    if failed > 0 {
+        anyhow::bail!("{failed} stream(s) failed");
+    }
     Ok(())
 }
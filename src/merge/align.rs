@@ -0,0 +1,199 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use super::Track;
+
+/// How to line up video and audio tracks that start or end at different times
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    /// Cut both tracks to the span covered by both.
+    #[default]
+    Trim,
+    /// Keep both tracks whole, the shorter one is left empty at the start or end.
+    Pad,
+}
+
+/// Span of the stream downloaded for one track
+#[derive(Clone, Debug, Serialize)]
+pub struct Coverage {
+    /// First PTS of the track in seconds.
+    pub start: f64,
+
+    /// Last PTS of the track in seconds.
+    pub end: f64,
+
+    /// Seconds of the track actually downloaded, excluding gaps.
+    pub covered: f64,
+}
+
+/// How video and audio were lined up when muxing
+#[derive(Clone, Debug, Serialize)]
+pub struct Alignment {
+    /// Coverage of the video track, `None` for audio-only output.
+    pub video: Option<Coverage>,
+
+    /// Coverage of the audio track.
+    pub audio: Coverage,
+
+    /// PTS in seconds the merged file starts at.
+    pub start: f64,
+
+    /// Duration of the merged file in seconds.
+    pub duration: f64,
+
+    /// Seconds the audio track starts after the video track, 0 for audio-only output.
+    pub audio_offset: f64,
+
+    /// Seconds audio lags behind video at the end of the merged file because of gaps joined
+    /// without filling.
+    pub drift: f64,
+}
+
+impl Alignment {
+    /// Seconds from the start of the earliest track to the start of the merged file
+    pub(super) fn skip(&self) -> f64 {
+        self.start - self.earliest()
+    }
+
+    /// Seconds from the start of the earliest track to the start of `coverage`
+    pub(super) fn offset(&self, coverage: &Coverage) -> f64 {
+        coverage.start - self.earliest()
+    }
+
+    /// Cut to the span between PTS `from` and `to` in seconds
+    pub(super) fn clip(&mut self, from: f64, to: f64) -> Result<()> {
+        let start = self.start.max(from);
+        let end = (self.start + self.duration).min(to);
+        if end <= start {
+            bail!("Range is outside the downloaded stream");
+        }
+        self.start = start;
+        self.duration = end - start;
+        Ok(())
+    }
+
+    fn earliest(&self) -> f64 {
+        match &self.video {
+            Some(video) => video.start.min(self.audio.start),
+            None => self.audio.start,
+        }
+    }
+}
+
+/// Compute the common span of the video and audio tracks
+pub(super) fn align(video: Option<&Track>, audio: &Track, mode: Align) -> Result<Alignment> {
+    // Gaps joined without filling shift everything after them earlier
+    let joined = |t: &Track| -> f64 {
+        t.gaps
+            .iter()
+            .filter(|g| !g.filled)
+            .map(|g| g.duration)
+            .sum()
+    };
+    let drift = video.map_or(0.0, |v| joined(v) - joined(audio));
+
+    let video = video.map(coverage);
+    let audio = coverage(audio);
+    let (start, end) = match (&video, mode) {
+        (None, _) => (audio.start, audio.end),
+        (Some(v), Align::Trim) => (v.start.max(audio.start), v.end.min(audio.end)),
+        (Some(v), Align::Pad) => (v.start.min(audio.start), v.end.max(audio.end)),
+    };
+    if end <= start {
+        bail!("Video and audio tracks don't overlap");
+    }
+
+    Ok(Alignment {
+        start,
+        duration: end - start,
+        audio_offset: video.as_ref().map_or(0.0, |v| audio.start - v.start),
+        drift,
+        video,
+        audio,
+    })
+}
+
+/// Shortest duration in seconds of a file merged from tracks with the given coverage.
+/// Gaps may have been joined without filling, which shortens a track by its missing time.
+pub(crate) fn merged_duration(
+    video: Option<&Coverage>,
+    audio: &Coverage,
+    mode: Align,
+) -> Result<f64> {
+    let track = |c: &Coverage| Track {
+        start: c.start,
+        end: c.end,
+        ..Default::default()
+    };
+    let alignment = align(video.map(track).as_ref(), &track(audio), mode)?;
+    let missing = video
+        .into_iter()
+        .chain([audio])
+        .map(|c| c.end - c.start - c.covered)
+        .fold(0.0, f64::max);
+    Ok(alignment.duration - missing)
+}
+
+fn coverage(track: &Track) -> Coverage {
+    let missing: f64 = track.gaps.iter().map(|g| g.duration).sum();
+    Coverage {
+        start: track.start,
+        end: track.end,
+        covered: track.end - track.start - missing,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::merge::test::write_track;
+    use crate::mpd::MediaType;
+
+    #[tokio::test]
+    async fn trim_and_pad() {
+        let dir = std::env::temp_dir().join(format!("iglive-align-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Video is missing 2s after 4s, joined without filling
+        let (_, video) = write_track(
+            MediaType::Video,
+            &[(10000, 14000), (16000, 30000)],
+            &dir.join("video.mp4"),
+            false,
+        )
+        .await;
+        let (_, audio) = write_track(
+            MediaType::Audio,
+            &[(12000, 31000)],
+            &dir.join("audio.mp4"),
+            false,
+        )
+        .await;
+
+        let trim = align(Some(&video), &audio, Align::Trim).unwrap();
+        assert_eq!((trim.start, trim.duration), (12.0, 18.0));
+        assert_eq!(trim.skip(), 2.0);
+        assert_eq!(trim.offset(&trim.audio), 2.0);
+        assert_eq!(trim.video.as_ref().unwrap().covered, 18.0);
+        assert_eq!(trim.drift, 2.0);
+
+        let pad = align(Some(&video), &audio, Align::Pad).unwrap();
+        assert_eq!((pad.start, pad.duration), (10.0, 21.0));
+        assert_eq!(pad.skip(), 0.0);
+
+        let (_, late) = write_track(
+            MediaType::Audio,
+            &[(40000, 50000)],
+            &dir.join("late.mp4"),
+            false,
+        )
+        .await;
+        assert!(align(Some(&video), &late, Align::Trim).is_err());
+
+        let audio_only = align(None, &audio, Align::Trim).unwrap();
+        assert_eq!((audio_only.skip(), audio_only.duration), (0.0, 19.0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::Result;
+
+use super::GapFill;
+use crate::error::IgLiveError;
+use crate::mpd::MediaType;
+use crate::pts::StreamInfo;
+
+/// Generate a file of `duration` seconds to insert into a gap of a track.
+///
+/// Video has the same resolution and frame rate as the track, audio is silence with the same
+/// sample rate and channels. The encoder settings can't match the original stream, so the track
+/// must be joined with [join_filled] rather than copied.
+///
+/// # Arguments
+///
+/// `previous` - File ending right before the gap, used for [GapFill::Freeze].
+pub fn generate_filler(
+    media_type: &MediaType,
+    fill: GapFill,
+    info: &StreamInfo,
+    duration: f64,
+    previous: &Path,
+    output: &Path,
+) -> Result<()> {
+    let mut cmd = process::Command::new("ffmpeg");
+    match media_type {
+        MediaType::Video => {
+            let width = info.width.unwrap_or(720);
+            let height = info.height.unwrap_or(1280);
+            let fps = info.r_frame_rate.as_deref().unwrap_or("30");
+
+            // Use the last frame before the gap, or black if it can't be extracted
+            let frame = output.with_extension("png");
+            let frozen = fill == GapFill::Freeze && extract_last_frame(previous, &frame);
+            if frozen {
+                cmd.args(["-loop", "1", "-framerate", fps])
+                    .args([OsStr::new("-i"), frame.as_os_str()])
+                    .args(["-vf", &format!("scale={width}:{height}")]);
+            } else {
+                cmd.args(["-f", "lavfi", "-i"])
+                    .arg(format!("color=c=black:s={width}x{height}:r={fps}"));
+            }
+
+            cmd.args(["-c:v", encoder(media_type, info)])
+                .args(["-pix_fmt", "yuv420p", "-r", fps, "-an"]);
+        }
+        _ => {
+            let rate = info.sample_rate.as_deref().unwrap_or("48000");
+            let layout = match info.channels {
+                Some(1) => "mono",
+                _ => "stereo",
+            };
+            cmd.args(["-f", "lavfi", "-i"])
+                .arg(format!("anullsrc=r={rate}:cl={layout}"));
+
+            cmd.args(["-c:a", encoder(media_type, info), "-vn"]);
+        }
+    }
+
+    let output_status = cmd
+        .args(["-t", &format!("{duration:.6}")])
+        .args(["-f", "mp4", "-y"])
+        .arg(output)
+        .output()?;
+    let _ = std::fs::remove_file(output.with_extension("png"));
+
+    if !output_status.status.success() {
+        return Err(IgLiveError::FfmpegFail.into());
+    }
+    Ok(())
+}
+
+/// Join runs of a track and the filler between them into `output`.
+///
+/// The pieces are decoded and re-encoded with ffmpeg's concat filter, since the filler's encoder
+/// parameters differ from the original stream and players can't switch between them mid-track.
+pub fn join_filled(
+    pieces: &[PathBuf],
+    media_type: &MediaType,
+    info: &StreamInfo,
+    output: &Path,
+) -> Result<()> {
+    let mut cmd = process::Command::new("ffmpeg");
+    for piece in pieces {
+        cmd.args([OsStr::new("-i"), piece.as_os_str()]);
+    }
+
+    // Make every input's frame or sample format identical, as required by the concat filter
+    let (kind, normalize, v, a) = match media_type {
+        MediaType::Video => ("v", "setsar=1,format=yuv420p", 1, 0),
+        _ => ("a", "aformat=sample_fmts=fltp", 0, 1),
+    };
+    let mut filter = String::new();
+    for i in 0..pieces.len() {
+        filter += &format!("[{i}:{kind}]{normalize}[in{i}];");
+    }
+    for i in 0..pieces.len() {
+        filter += &format!("[in{i}]");
+    }
+    filter += &format!("concat=n={}:v={v}:a={a}[out]", pieces.len());
+    cmd.args(["-filter_complex", &filter, "-map", "[out]"]);
+
+    match media_type {
+        MediaType::Video => {
+            cmd.args(["-c:v", encoder(media_type, info)])
+                .args(["-crf", "18", "-preset", "veryfast", "-pix_fmt", "yuv420p"]);
+        }
+        _ => {
+            cmd.args(["-c:a", encoder(media_type, info), "-b:a", "192k"]);
+        }
+    }
+
+    let output_status = cmd.args(["-f", "mp4", "-y"]).arg(output).output()?;
+    if !output_status.status.success() {
+        return Err(IgLiveError::FfmpegFail.into());
+    }
+    Ok(())
+}
+
+/// Encoder producing the same codec as the track
+fn encoder(media_type: &MediaType, info: &StreamInfo) -> &'static str {
+    match (media_type, info.codec_name.as_str()) {
+        (MediaType::Video, "hevc") => "libx265",
+        (MediaType::Video, _) => "libx264",
+        (_, "opus") => "libopus",
+        _ => "aac",
+    }
+}
+
+fn extract_last_frame(input: &Path, output: &Path) -> bool {
+    process::Command::new("ffmpeg")
+        .args(["-sseof", "-1"])
+        .args([OsStr::new("-i"), input.as_os_str()])
+        .args(["-update", "1", "-y"])
+        .arg(output)
+        .output()
+        .is_ok_and(|o| o.status.success())
+        && output.exists()
+}
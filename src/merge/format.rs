@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// Container of the merged file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// MP4 with the index at the start of the file.
+    #[default]
+    Mp4,
+    /// Fragmented MP4, readable while incomplete.
+    Fmp4,
+    /// Matroska.
+    Mkv,
+    /// MPEG transport stream.
+    Ts,
+    /// Audio-only MP4.
+    M4a,
+}
+
+impl OutputFormat {
+    /// File extension without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp4 | Self::Fmp4 => "mp4",
+            Self::Mkv => "mkv",
+            Self::Ts => "ts",
+            Self::M4a => "m4a",
+        }
+    }
+
+    /// Whether the format contains the video track
+    pub fn has_video(&self) -> bool {
+        *self != Self::M4a
+    }
+
+    /// ffmpeg output options selecting the muxer
+    pub(super) fn muxer_args(&self) -> &'static [&'static str] {
+        match self {
+            Self::Mp4 => &["-f", "mp4", "-movflags", "+faststart+use_metadata_tags"],
+            Self::Fmp4 => &[
+                "-f",
+                "mp4",
+                "-movflags",
+                "+frag_keyframe+empty_moov+default_base_moof+use_metadata_tags",
+            ],
+            Self::Mkv => &["-f", "matroska"],
+            Self::Ts => &["-f", "mpegts"],
+            Self::M4a => &["-f", "ipod", "-movflags", "+faststart+use_metadata_tags"],
+        }
+    }
+
+    /// ffmpeg options for writing parts in this format with the segment muxer
+    pub(super) fn segment_args(&self) -> Vec<String> {
+        let args = self.muxer_args();
+        let mut segment_args = vec!["-segment_format".to_owned(), args[1].to_owned()];
+        if let [_, _, "-movflags", flags] = args {
+            segment_args.push("-segment_format_options".to_owned());
+            segment_args.push(format!("movflags={flags}"));
+        }
+        segment_args
+    }
+}
+
+/// Default template for the merged file name
+pub const DEFAULT_OUTPUT_NAME: &str = "{name}.{ext}";
+
+/// Build the merged file path from a name template.
+///
+/// `{name}` is replaced with the name of the download directory, `{ext}` with the extension of
+/// `format`. A relative result is placed in `output_dir`.
+pub(super) fn output_path(
+    template: &str,
+    name: &str,
+    format: OutputFormat,
+    output_dir: &Path,
+) -> PathBuf {
+    let file = template
+        .replace("{name}", name)
+        .replace("{ext}", format.extension());
+    output_dir.join(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn output_names() {
+        let dir = Path::new("out");
+        assert_eq!(
+            output_path(DEFAULT_OUTPUT_NAME, "123", OutputFormat::Mkv, dir),
+            Path::new("out/123.mkv")
+        );
+        assert_eq!(
+            output_path("lives/{name}-audio.{ext}", "123", OutputFormat::M4a, dir),
+            Path::new("out/lives/123-audio.m4a")
+        );
+        assert_eq!(
+            output_path("/tmp/{name}.{ext}", "123", OutputFormat::Ts, dir),
+            Path::new("/tmp/123.ts")
+        );
+    }
+}
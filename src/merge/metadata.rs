@@ -0,0 +1,199 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat};
+use serde::Serialize;
+
+use super::{Gap, Track};
+use crate::mpd::MediaType;
+use crate::store::StreamMeta;
+
+/// Name and version of this tool
+const TOOL: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+/// Information about where a merged file came from, written as tags and to a JSON file
+#[derive(Clone, Debug, Serialize)]
+pub struct Metadata {
+    /// Stream ID from the manifest, if recorded while downloading.
+    pub stream_id: Option<String>,
+
+    /// Estimated time the broadcast started at in RFC 3339, if recorded while downloading.
+    pub broadcast_start: Option<String>,
+
+    /// Name and version of the tool that merged the file.
+    pub tool: String,
+
+    /// Representations the tracks were downloaded from.
+    pub representations: Vec<RepresentationInfo>,
+
+    /// Summary of missing segments.
+    pub gaps: GapSummary,
+}
+
+/// Details of a representation a track was downloaded from
+#[derive(Clone, Debug, Serialize)]
+pub struct RepresentationInfo {
+    /// Track media type.
+    pub media_type: MediaType,
+
+    /// Representation ID.
+    pub id: String,
+
+    /// Codec name.
+    pub codec: String,
+
+    /// Video width in pixels.
+    pub width: Option<usize>,
+
+    /// Video height in pixels.
+    pub height: Option<usize>,
+
+    /// Video frame rate as a fraction.
+    pub frame_rate: Option<String>,
+
+    /// Audio sample rate in Hz.
+    pub sample_rate: Option<String>,
+
+    /// Number of audio channels.
+    pub channels: Option<usize>,
+}
+
+/// Summary of missing segments in all tracks
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GapSummary {
+    /// Number of gaps.
+    pub count: usize,
+
+    /// Total length of the gaps in seconds.
+    pub duration: f64,
+
+    /// Number of gaps filled with generated media.
+    pub filled: usize,
+}
+
+impl Metadata {
+    /// Collect metadata for merging `tracks`
+    pub(super) fn new(
+        meta: Option<StreamMeta>,
+        tracks: &[(MediaType, &Track)],
+        gaps: &[Gap],
+    ) -> Self {
+        let broadcast_start = meta.as_ref().and_then(|m| {
+            DateTime::from_timestamp_millis((m.broadcast_start * 1000.0) as i64)
+                .map(|d| d.to_rfc3339_opts(SecondsFormat::Secs, true))
+        });
+        let representations = tracks
+            .iter()
+            .map(|(media_type, track)| RepresentationInfo {
+                media_type: media_type.clone(),
+                id: track.representation.clone(),
+                codec: track.info.codec_name.clone(),
+                width: track.info.width,
+                height: track.info.height,
+                // ffprobe reports 0/0 for audio
+                frame_rate: track.info.r_frame_rate.clone().filter(|r| r != "0/0"),
+                sample_rate: track.info.sample_rate.clone(),
+                channels: track.info.channels,
+            })
+            .collect();
+        let gaps = GapSummary {
+            count: gaps.len(),
+            duration: gaps.iter().map(|g| g.duration).sum(),
+            filled: gaps.iter().filter(|g| g.filled).count(),
+        };
+
+        Self {
+            stream_id: meta.map(|m| m.id),
+            broadcast_start,
+            tool: TOOL.to_owned(),
+            representations,
+            gaps,
+        }
+    }
+
+    /// Container tags as key and value pairs
+    pub(super) fn tags(&self) -> Vec<(String, String)> {
+        let mut tags = vec![("iglive_tool".to_owned(), self.tool.clone())];
+        if let Some(id) = &self.stream_id {
+            tags.push(("iglive_stream_id".to_owned(), id.clone()));
+        }
+        if let Some(start) = &self.broadcast_start {
+            tags.push(("creation_time".to_owned(), start.clone()));
+            tags.push(("iglive_broadcast_start".to_owned(), start.clone()));
+        }
+        for rep in &self.representations {
+            let mut details = format!("{} {}", rep.id, rep.codec);
+            if let (Some(w), Some(h)) = (rep.width, rep.height) {
+                details += &format!(" {w}x{h}");
+            }
+            if let Some(fps) = &rep.frame_rate {
+                details += &format!(" {fps} fps");
+            }
+            if let Some(rate) = &rep.sample_rate {
+                details += &format!(" {rate} Hz");
+            }
+            if let Some(channels) = rep.channels {
+                details += &format!(" {channels} ch");
+            }
+            let key = serde_json::to_value(&rep.media_type)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_owned))
+                .unwrap_or_default();
+            tags.push((format!("iglive_{key}"), details));
+        }
+        tags.push((
+            "iglive_gaps".to_owned(),
+            format!(
+                "{} gaps, {:.3}s missing, {} filled",
+                self.gaps.count, self.gaps.duration, self.gaps.filled
+            ),
+        ));
+        tags
+    }
+
+    /// Write as pretty-printed JSON to `path`
+    pub(super) fn write_json(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::merge::test::write_track;
+    use crate::pts::StreamInfo;
+
+    #[tokio::test]
+    async fn metadata_tags() {
+        let dir = std::env::temp_dir().join(format!("iglive-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Default merge of a track with a segment missing
+        let segments = [(0, 2000), (2000, 4000), (6000, 8000)];
+        let (_, mut track) =
+            write_track(MediaType::Audio, &segments, &dir.join("audio.mp4"), false).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        track.representation = "audio-1".to_owned();
+        track.info = StreamInfo {
+            codec_name: "aac".to_owned(),
+            r_frame_rate: Some("0/0".to_owned()),
+            sample_rate: Some("48000".to_owned()),
+            channels: Some(2),
+            ..Default::default()
+        };
+        let meta = StreamMeta {
+            id: "123".to_owned(),
+            publish_frame_time: 0,
+            broadcast_start: 1688212800.0,
+        };
+
+        let metadata = Metadata::new(Some(meta), &[(MediaType::Audio, &track)], &track.gaps);
+        let tags = metadata.tags();
+        let tag = |key: &str| tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        assert_eq!(tag("iglive_stream_id"), Some("123"));
+        assert_eq!(tag("creation_time"), Some("2023-07-01T12:00:00Z"));
+        assert_eq!(tag("iglive_audio"), Some("audio-1 aac 48000 Hz 2 ch"));
+        assert_eq!(tag("iglive_gaps"), Some("1 gaps, 2.000s missing, 0 filled"));
+    }
+}
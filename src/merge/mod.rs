@@ -0,0 +1,796 @@
+mod align;
+mod fill;
+mod format;
+mod metadata;
+mod recording;
+mod split;
+mod transcode;
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+
+use anyhow::{bail, Result};
+use futures::join;
+use serde::Serialize;
+
+use self::align::align;
+pub(crate) use self::align::merged_duration;
+pub use self::align::{Align, Alignment, Coverage};
+use self::fill::{generate_filler, join_filled};
+use self::format::output_path;
+pub use self::format::{OutputFormat, DEFAULT_OUTPUT_NAME};
+pub use self::metadata::{GapSummary, Metadata, RepresentationInfo};
+use self::recording::merge_recording;
+pub(crate) use self::recording::Recorder;
+pub use self::recording::RECORDING_NAME;
+pub use self::split::SplitConfig;
+use self::split::{part_template, split_file, split_points};
+use self::transcode::transcode;
+pub use self::transcode::{TranscodeProfile, VideoCodec, PROFILES};
+use crate::error::IgLiveError;
+use crate::mp4::init_info;
+use crate::mpd::MediaType;
+use crate::pts::{get_pts, get_start_time, get_stream_info, get_timescale, StreamInfo};
+use crate::store::{get_with_init, index_by_key, FsStore, IndexEntry, SegmentKey, SegmentStore};
+
+/// Options for merge
+#[derive(Clone, Debug, Default)]
+pub struct MergeConfig {
+    /// Insert generated video and silent audio of the missing duration in place of missing
+    /// segments, so video and audio stay aligned.
+    /// If `None`, segments on both sides of a gap are joined directly.
+    pub fill_gaps: Option<GapFill>,
+
+    /// How to line up video and audio tracks that start or end at different times.
+    pub align: Align,
+
+    /// Container of the merged file.
+    pub format: OutputFormat,
+
+    /// Directory to write the merged file to.
+    /// If `None`, use the directory the stream was downloaded to.
+    pub output_dir: Option<PathBuf>,
+
+    /// Template for the merged file name, relative to the output directory.
+    /// `{name}` is replaced with the name of the download directory and `{ext}` with the
+    /// extension of the format.
+    /// If `None`, use [DEFAULT_OUTPUT_NAME].
+    pub output_name: Option<String>,
+
+    /// Re-encode the merged file with this profile.
+    /// If `None`, video and audio are copied without re-encoding.
+    pub transcode: Option<TranscodeProfile>,
+
+    /// Split the merged file into parts at segment boundaries.
+    /// Parts are named with the output name template, where `{part}` is replaced with the part
+    /// number. If the template has no `{part}`, `-{part}` is added before the extension.
+    pub split: Option<SplitConfig>,
+}
+
+impl MergeConfig {
+    /// Path of the file merged from the download directory `dir`, before splitting into parts
+    pub fn output_path(&self, dir: &Path) -> PathBuf {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        output_path(
+            self.output_name.as_deref().unwrap_or(DEFAULT_OUTPUT_NAME),
+            &name,
+            self.format,
+            self.output_dir.as_deref().unwrap_or(dir),
+        )
+    }
+}
+
+/// Video inserted in place of missing segments
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GapFill {
+    /// Black frames.
+    Black,
+    /// Repeat the last frame before the gap.
+    Freeze,
+}
+
+/// Result of merge
+#[derive(Clone, Debug, Serialize)]
+pub struct MergeReport {
+    /// Path of the merged file, or of the first part if split.
+    pub output: PathBuf,
+
+    /// Paths of the parts in order if split, otherwise empty.
+    pub parts: Vec<PathBuf>,
+
+    /// Path of the playlist listing the parts, if written.
+    pub playlist: Option<PathBuf>,
+
+    /// Missing spans found in the video and audio tracks.
+    pub gaps: Vec<Gap>,
+
+    /// How video and audio were lined up.
+    pub alignment: Alignment,
+
+    /// Metadata written to the merged file.
+    pub metadata: Metadata,
+}
+
+/// Span of a track with missing segments
+#[derive(Clone, Debug, Serialize)]
+pub struct Gap {
+    /// Track the gap is in.
+    pub media_type: MediaType,
+
+    /// Start of the gap in seconds from the start of the track.
+    pub start: f64,
+
+    /// Length of the gap in seconds.
+    pub duration: f64,
+
+    /// Whether the gap was filled with generated media.
+    pub filled: bool,
+}
+
+/// Concatenated video or audio track
+#[derive(Default)]
+struct Track {
+    gaps: Vec<Gap>,
+    /// ID of the first segment's representation.
+    representation: String,
+    /// Codec parameters from the first segment's initialization data.
+    info: StreamInfo,
+    /// First PTS in seconds.
+    start: f64,
+    /// Last PTS in seconds.
+    end: f64,
+    /// Segment time of the first segment.
+    t_start: usize,
+}
+
+/// Contiguous segments of a track written to one file
+struct Run {
+    path: PathBuf,
+    pts_start: usize,
+    pts_end: usize,
+}
+
+/// Merge video and audio segments downloaded by [download][crate::download::download] into a
+/// single video file.
+/// `ffmpeg` is required in `$PATH`.
+///
+/// The output file will be placed in `dir` unless [MergeConfig::output_dir] is set.
+///
+/// # Arguments
+///
+/// `dir` - Directory containing downloaded video and audio segments.
+pub async fn merge(dir: impl AsRef<Path>, config: &MergeConfig) -> Result<MergeReport> {
+    merge_from(&FsStore::new(dir.as_ref()), dir, config).await
+}
+
+/// Merge video and audio segments from `store` into a single video file.
+/// `ffmpeg` is required in `$PATH`.
+///
+/// The output file will be placed in `dir` unless [MergeConfig::output_dir] is set.
+///
+/// If the download left a [RECORDING_NAME] of live segments, only the segments before it are
+/// merged and joined with it.
+///
+/// # Arguments
+///
+/// `store` - Store containing downloaded video and audio segments.
+/// `dir` - Directory the stream was downloaded to, its name is used for the output file name.
+pub async fn merge_from(
+    store: &dyn SegmentStore,
+    dir: impl AsRef<Path>,
+    config: &MergeConfig,
+) -> Result<MergeReport> {
+    match merge_recording(store, dir.as_ref(), config).await {
+        Ok(Some(report)) => return Ok(report),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("WARNING: Failed to merge from {RECORDING_NAME}: {e}, merging all segments")
+        }
+    }
+    merge_range(store, dir, config, None).await
+}
+
+/// Merge only the segments overlapping `range` of segment times, and cut the output to it.
+/// See [merge_from].
+pub(crate) async fn merge_range(
+    store: &dyn SegmentStore,
+    dir: impl AsRef<Path>,
+    config: &MergeConfig,
+    range: Option<Range<usize>>,
+) -> Result<MergeReport> {
+    println!("Merging video file");
+
+    // List all segments, already sorted by time
+    let meta = store.get_meta().await?;
+    let segments = store.list_segments().await?;
+    let index = index_by_key(store.read_index().await?);
+    let (video_segments, audio_segments): (Vec<_>, Vec<_>) = segments
+        .iter()
+        .filter(|k| k.media_type != MediaType::Unknown)
+        .partition(|k| k.media_type == MediaType::Video);
+    let (video_segments, audio_segments) = match &range {
+        Some(range) => (
+            overlapping(video_segments, &index, range),
+            overlapping(audio_segments, &index, range),
+        ),
+        None => (video_segments, audio_segments),
+    };
+
+    // Output paths
+    let file_name_base = dir
+        .as_ref()
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let output_dir = config.output_dir.as_deref().unwrap_or(dir.as_ref());
+    let output_name = config.output_name.as_deref().unwrap_or(DEFAULT_OUTPUT_NAME);
+    let output_path = config.output_path(dir.as_ref());
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let merged_path = match config.split {
+        Some(_) => output_dir.join(file_name_base.clone() + "merged.tmp"),
+        None => output_path.clone(),
+    };
+
+    // Concatenate segments
+    let video_concat = output_dir.join(file_name_base.clone() + "video.tmp");
+    let audio_concat = output_dir.join(file_name_base.clone() + "audio.tmp");
+    let (video, audio) = join!(
+        async {
+            if !config.format.has_video() {
+                return Ok(None);
+            }
+            merge_segments(store, &index, &video_segments, &video_concat, config)
+                .await
+                .map(Some)
+        },
+        merge_segments(store, &index, &audio_segments, &audio_concat, config),
+    );
+    let result = match (video, audio) {
+        (Ok(video), Ok(audio)) => {
+            // Offset from segment times in seconds to PTS in seconds
+            let reference = video.as_ref().unwrap_or(&audio);
+            let pts_offset = reference.start - reference.t_start as f64 / 1000.0;
+
+            let gaps = [
+                video.as_ref().map(|v| v.gaps.clone()).unwrap_or_default(),
+                audio.gaps.clone(),
+            ]
+            .concat();
+            let mut tracks = vec![];
+            if let Some(video) = &video {
+                tracks.push((MediaType::Video, video));
+            }
+            tracks.push((MediaType::Audio, &audio));
+            let metadata = Metadata::new(meta, &tracks, &gaps);
+
+            let video = video.as_ref().map(|v| (v, video_concat.as_path()));
+            let tags = metadata.tags();
+            mux(
+                video,
+                (&audio, &audio_concat),
+                &merged_path,
+                config,
+                range,
+                &tags,
+            )
+            .await
+            .map(|alignment| (alignment, gaps, pts_offset, metadata))
+        }
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+
+    // Remove concatenated files
+    let _ = fs::remove_file(video_concat);
+    let _ = fs::remove_file(audio_concat);
+
+    let (alignment, gaps, pts_offset, metadata) = result?;
+    metadata.write_json(&output_path.with_extension("metadata.json"))?;
+
+    // Split into parts
+    let (output_path, parts, playlist) = match &config.split {
+        Some(split) => {
+            // Split at the start of video segments, or audio segments for audio-only output
+            let reference = if config.format.has_video() {
+                &video_segments
+            } else {
+                &audio_segments
+            };
+            let start = |key: &SegmentKey| key.t as f64 / 1000.0 + pts_offset - alignment.start;
+            let mut units = vec![(0.0, 0)];
+            units.extend(
+                reference
+                    .iter()
+                    .map(|k| (start(k), 0))
+                    .filter(|&(s, _)| s > 0.0 && s < alignment.duration),
+            );
+            let video_segments = video_segments.iter().filter(|_| config.format.has_video());
+            for key in video_segments.chain(&audio_segments) {
+                let size = match index.get(key) {
+                    Some(e) => e.size,
+                    None => store.get_segment(key).await?.len(),
+                };
+                let i = units.partition_point(|&(s, _)| s <= start(key)).max(1) - 1;
+                units[i].1 += size as u64;
+            }
+            let points = split_points(&units, alignment.duration, split);
+
+            let template = part_template(output_name);
+            let pattern =
+                self::format::output_path(&template, &file_name_base, config.format, output_dir);
+            let playlist = split.playlist.then(|| output_path.with_extension("m3u8"));
+            let result = split_file(
+                &merged_path,
+                &points,
+                config.format,
+                &pattern,
+                playlist.as_deref(),
+            );
+            let _ = fs::remove_file(&merged_path);
+            let parts = result?;
+            let first = parts.first().cloned().unwrap_or(output_path);
+            (first, parts, playlist)
+        }
+        None => (output_path, vec![], None),
+    };
+
+    for gap in gaps.iter().filter(|g| g.filled) {
+        println!(
+            "Filled {:?} gap at {:.3}s ({:.3}s)",
+            gap.media_type, gap.start, gap.duration
+        );
+    }
+    print_alignment(&alignment, config.align);
+    match parts.len() {
+        0 => println!("Merged video written to {:?}", output_path),
+        n => println!(
+            "Merged video written to {n} parts starting with {:?}",
+            output_path
+        ),
+    }
+    Ok(MergeReport {
+        output: output_path,
+        parts,
+        playlist,
+        gaps,
+        alignment,
+        metadata,
+    })
+}
+
+/// Mux the concatenated tracks into the final file, offsetting each track to its start time and
+/// cutting to the common span
+async fn mux(
+    video: Option<(&Track, &Path)>,
+    audio: (&Track, &Path),
+    output_path: &Path,
+    config: &MergeConfig,
+    range: Option<Range<usize>>,
+    tags: &[(String, String)],
+) -> Result<Alignment> {
+    let mut alignment = align(video.map(|(v, _)| v), audio.0, config.align)?;
+
+    // Convert the range from segment times in milliseconds to PTS
+    if let Some(range) = &range {
+        let track = video.map_or(audio.0, |(v, _)| v);
+        let pts = |t: usize| track.start + (t as f64 - track.t_start as f64) / 1000.0;
+        alignment.clip(pts(range.start), pts(range.end))?;
+    }
+
+    // Timestamps in the concatenated files may not match the original PTS, so shift each input
+    // from wherever it starts to its offset from the earliest track
+    let mut args: Vec<OsString> = vec![];
+    let inputs = [
+        video
+            .zip(alignment.video.as_ref())
+            .map(|((_, p), c)| (p, c)),
+        Some((audio.1, &alignment.audio)),
+    ];
+    for (path, coverage) in inputs.into_iter().flatten() {
+        let file_start = get_start_time(path).await.unwrap_or(0.0);
+        let offset = alignment.offset(coverage) - file_start;
+        args.extend(["-itsoffset".into(), format!("{offset:.6}").into()]);
+        args.extend(["-i".into(), path.into()]);
+    }
+    if config.align == Align::Trim || range.is_some() {
+        args.extend(["-ss".into(), format!("{:.6}", alignment.skip()).into()]);
+        args.extend(["-t".into(), format!("{:.6}", alignment.duration).into()]);
+    }
+    for (key, value) in tags {
+        args.extend(["-metadata".into(), format!("{key}={value}").into()]);
+    }
+
+    if let Some(profile) = &config.transcode {
+        transcode(
+            &args,
+            profile,
+            config.format.has_video(),
+            alignment.duration,
+            config.format.muxer_args(),
+            output_path,
+        )
+        .await?;
+        return Ok(alignment);
+    }
+
+    let output = process::Command::new("ffmpeg")
+        .args(args)
+        .args(["-c", "copy"])
+        .args(config.format.muxer_args())
+        .arg("-y")
+        .arg(output_path)
+        .output()?;
+    if !output.status.success() {
+        return Err(IgLiveError::FfmpegFail.into());
+    }
+    Ok(alignment)
+}
+
+fn print_alignment(alignment: &Alignment, mode: Align) {
+    let tracks = [
+        alignment.video.as_ref().map(|c| ("Video", c)),
+        Some(("Audio", &alignment.audio)),
+    ];
+    for (name, c) in tracks.into_iter().flatten() {
+        println!(
+            "{name}: {:.3}s to {:.3}s, {:.3}s downloaded",
+            c.start, c.end, c.covered
+        );
+    }
+    let Some(video) = &alignment.video else {
+        return;
+    };
+    println!(
+        "Audio starts {:+.3}s and ends {:+.3}s from video, {:?} to {:.3}s",
+        alignment.audio_offset,
+        alignment.audio.end - video.end,
+        mode,
+        alignment.duration
+    );
+    if alignment.drift.abs() > 0.0005 {
+        println!(
+            "WARNING: audio drifts {:+.3}s against video by the end due to unfilled gaps",
+            alignment.drift
+        );
+    }
+}
+
+/// Concatenate the segments of one track into `path`.
+/// If filling gaps, contiguous runs are written to separate files and joined with filler.
+async fn merge_segments(
+    store: &dyn SegmentStore,
+    index: &HashMap<SegmentKey, IndexEntry>,
+    segs: &[&SegmentKey],
+    path: impl AsRef<Path>,
+    config: &MergeConfig,
+) -> Result<Track> {
+    let path = path.as_ref();
+    let written = write_runs(store, index, segs, path, config.fill_gaps.is_some()).await?;
+    let runs = &written.runs;
+
+    // Find gaps between segments
+    let timescale = match init_info(&written.init) {
+        Some(info) => info.timescale as f64,
+        None => get_timescale(written.init.clone()).await? as f64,
+    };
+    let info = get_stream_info(written.init.clone()).await?;
+    let mut track = written.track(info, timescale);
+
+    let Some(fill) = config.fill_gaps.filter(|_| runs.len() > 1) else {
+        fs::rename(&runs[0].path, path)?;
+        return Ok(track);
+    };
+
+    // Generate filler for each gap and join everything
+    let mut pieces = vec![];
+    let mut result = Ok(());
+    for (i, run) in runs.iter().enumerate() {
+        pieces.push(run.path.clone());
+        let Some(gap) = track.gaps.get_mut(i) else {
+            continue;
+        };
+        let filler = path.with_extension(format!("fill{i}.tmp"));
+        result = generate_filler(
+            &written.media_type,
+            fill,
+            &track.info,
+            gap.duration,
+            &run.path,
+            &filler,
+        );
+        if result.is_err() {
+            break;
+        }
+        gap.filled = true;
+        pieces.push(filler);
+    }
+    let result = result.and_then(|_| join_filled(&pieces, &written.media_type, &track.info, path));
+    // Also remove runs left over if generating a filler failed
+    for piece in pieces.iter().chain(runs.iter().map(|r| &r.path)) {
+        let _ = fs::remove_file(piece);
+    }
+    result?;
+
+    Ok(track)
+}
+
+/// Segments of one track written to run files by [write_runs]
+struct WrittenRuns {
+    media_type: MediaType,
+    representation: String,
+    /// Time of the first segment.
+    t_start: usize,
+    /// Initialization data of the first segment.
+    init: Vec<u8>,
+    /// Contiguous runs of segments, split at every missing segment.
+    runs: Vec<Run>,
+}
+
+/// Write the segments of one track, starting a new run after every missing segment.
+/// If `separate`, each run is written to its own file, otherwise they share one file.
+async fn write_runs(
+    store: &dyn SegmentStore,
+    index: &HashMap<SegmentKey, IndexEntry>,
+    segs: &[&SegmentKey],
+    path: &Path,
+    separate: bool,
+) -> Result<WrittenRuns> {
+    let mut runs: Vec<Run> = vec![];
+    let mut output: Option<fs::File> = None;
+    let mut written_init: Option<Vec<u8>> = None;
+    let mut first_init: Option<Vec<u8>> = None;
+    let mut media_type = MediaType::Unknown;
+    let mut t_start = None;
+    let mut representation = String::new();
+
+    // Write segments
+    for &key in segs {
+        media_type = key.media_type.clone();
+        if t_start.is_none() {
+            t_start = Some(key.t);
+            representation = key.representation.clone();
+        }
+        let (init, fragment) = get_with_init(store, key).await?;
+        let init = init.ok_or(IgLiveError::MissingInit)?;
+        first_init.get_or_insert_with(|| init.clone());
+
+        // Use recorded PTS if available, otherwise probe the segment
+        let cur_pts = match index.get(key) {
+            Some(e) if e.size == fragment.len() => (e.pts_start, e.pts_end),
+            _ => get_pts([init.clone(), fragment.clone()].concat()).await?,
+        };
+
+        // Start a new run after a gap
+        let new_run = match runs.last() {
+            None => true,
+            Some(run) => cur_pts.0 > run.pts_end,
+        };
+        if let Some(run) = runs.last() {
+            if run.pts_end != cur_pts.0 {
+                eprintln!("WARNING: Missing segment at PTS={}", run.pts_end);
+            }
+        }
+        if new_run {
+            let run_path = match runs.last() {
+                Some(run) if !separate => run.path.clone(),
+                _ => {
+                    let run_path = path.with_extension(format!("{}.tmp", runs.len()));
+                    output = Some(fs::File::create(&run_path)?);
+                    written_init = None;
+                    run_path
+                }
+            };
+            runs.push(Run {
+                path: run_path,
+                pts_start: cur_pts.0,
+                pts_end: cur_pts.1,
+            });
+        }
+        let file = output.as_mut().unwrap();
+        let run = runs.last_mut().unwrap();
+
+        // Only write initialization data when it changes
+        if written_init.as_ref() != Some(&init) {
+            file.write_all(&init)?;
+            written_init = Some(init);
+        }
+        file.write_all(&fragment)?;
+        run.pts_end = cur_pts.1;
+    }
+    drop(output);
+
+    let (Some(init), Some(t_start)) = (first_init, t_start) else {
+        bail!("No {:?} segments to merge", media_type);
+    };
+    Ok(WrittenRuns {
+        media_type,
+        representation,
+        t_start,
+        init,
+        runs,
+    })
+}
+
+impl WrittenRuns {
+    /// Describe the written track, with an unfilled gap between each pair of runs
+    fn track(&self, info: StreamInfo, timescale: f64) -> Track {
+        let runs = &self.runs;
+        let track_start = runs[0].pts_start;
+        let gaps = runs
+            .windows(2)
+            .filter(|pair| pair[1].pts_start > pair[0].pts_end)
+            .map(|pair| Gap {
+                media_type: self.media_type.clone(),
+                start: (pair[0].pts_end - track_start) as f64 / timescale,
+                duration: (pair[1].pts_start - pair[0].pts_end) as f64 / timescale,
+                filled: false,
+            })
+            .collect();
+        Track {
+            representation: self.representation.clone(),
+            info,
+            t_start: self.t_start,
+            start: track_start as f64 / timescale,
+            end: runs[runs.len() - 1].pts_end as f64 / timescale,
+            gaps,
+        }
+    }
+}
+
+/// Segments of one track overlapping `range`, each segment lasting until the next one starts
+fn overlapping<'a>(
+    segs: Vec<&'a SegmentKey>,
+    index: &HashMap<SegmentKey, IndexEntry>,
+    range: &Range<usize>,
+) -> Vec<&'a SegmentKey> {
+    segs.iter()
+        .enumerate()
+        .filter(|(i, key)| {
+            let end = segs
+                .get(i + 1)
+                .filter(|next| next.representation == key.representation)
+                .map(|next| next.t)
+                .or_else(|| index.get(key).and_then(|e| e.d).map(|d| key.t + d))
+                .unwrap_or(usize::MAX);
+            key.t < range.end && end > range.start
+        })
+        .map(|(_, key)| *key)
+        .collect()
+}
+
+/// Input file for ffmpeg's concat demuxer listing `files`
+fn concat_list(files: &[PathBuf]) -> Result<String> {
+    let mut list = String::new();
+    for file in files {
+        let file = fs::canonicalize(file)?;
+        let file = file.to_string_lossy().replace('\'', "'\\''");
+        list.push_str(&format!("file '{file}'\n"));
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use super::*;
+    use crate::store::{MemoryStore, SegmentSource};
+
+    /// Write segments lasting `pts` through [write_runs] into `path` and describe the track like
+    /// [merge_segments] does, with PTS in milliseconds
+    pub(in crate::merge) async fn write_track(
+        media_type: MediaType,
+        pts: &[(usize, usize)],
+        path: &Path,
+        separate: bool,
+    ) -> (WrittenRuns, Track) {
+        let store = MemoryStore::new();
+        store.put_init(&media_type, "rep", b"init").await.unwrap();
+        let mut index = HashMap::new();
+        let mut keys = vec![];
+        for &(pts_start, pts_end) in pts {
+            let key = SegmentKey {
+                media_type: media_type.clone(),
+                representation: "rep".to_owned(),
+                t: pts_start,
+            };
+            let fragment = format!("{pts_start}|");
+            store.put_segment(&key, fragment.as_bytes()).await.unwrap();
+            index.insert(
+                key.clone(),
+                IndexEntry {
+                    media_type: media_type.clone(),
+                    representation: "rep".to_owned(),
+                    t: pts_start,
+                    d: None,
+                    pts_start,
+                    pts_end,
+                    size: fragment.len(),
+                    sha256: String::new(),
+                    source: SegmentSource::Live,
+                    downloaded_at: 0,
+                    url: String::new(),
+                },
+            );
+            keys.push(key);
+        }
+        let keys: Vec<_> = keys.iter().collect();
+        let written = write_runs(&store, &index, &keys, path, separate)
+            .await
+            .unwrap();
+        let track = written.track(StreamInfo::default(), 1000.0);
+        (written, track)
+    }
+
+    #[tokio::test]
+    async fn missing_segments() {
+        let dir = std::env::temp_dir().join(format!("iglive-runs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let segments = [(0, 2000), (2000, 4000), (6000, 8000), (8000, 9000)];
+
+        // Without filling, gaps are recorded but everything stays in one file
+        let path = dir.join("joined.mp4");
+        let (written, track) = write_track(MediaType::Video, &segments, &path, false).await;
+        assert_eq!(written.runs.len(), 2);
+        assert_eq!(written.runs[0].path, written.runs[1].path);
+        assert_eq!(
+            fs::read(&written.runs[0].path).unwrap(),
+            b"init0|2000|6000|8000|"
+        );
+        assert_eq!((track.start, track.end), (0.0, 9.0));
+        assert_eq!(track.gaps.len(), 1);
+        assert_eq!((track.gaps[0].start, track.gaps[0].duration), (4.0, 2.0));
+        assert!(!track.gaps[0].filled);
+
+        // When filling, each run gets its own file to put filler between
+        let path = dir.join("filled.mp4");
+        let (written, _) = write_track(MediaType::Video, &segments, &path, true).await;
+        assert_eq!(fs::read(&written.runs[1].path).unwrap(), b"init6000|8000|");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_output_path() {
+        let config = MergeConfig {
+            format: OutputFormat::Mkv,
+            output_dir: Some("archive".into()),
+            output_name: Some("{name}-full.{ext}".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.output_path(Path::new("lives/123")),
+            Path::new("archive/123-full.mkv")
+        );
+        assert_eq!(
+            MergeConfig::default().output_path(Path::new("lives/123")),
+            Path::new("lives/123/123.mp4")
+        );
+    }
+
+    #[test]
+    fn overlapping_segments() {
+        let keys: Vec<_> = [0, 2000, 4000, 6000]
+            .into_iter()
+            .map(|t| SegmentKey {
+                media_type: MediaType::Video,
+                representation: "rep".to_owned(),
+                t,
+            })
+            .collect();
+        let times = |range| {
+            overlapping(keys.iter().collect(), &HashMap::new(), &range)
+                .iter()
+                .map(|k| k.t)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(2500..4000), [2000]);
+        assert_eq!(times(1999..4001), [0, 2000, 4000]);
+        assert_eq!(times(7000..9000), [6000]);
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use super::align::align;
+use super::format::output_path;
+use super::{
+    concat_list, merge_range, print_alignment, Align, MergeConfig, MergeReport, Metadata, Track,
+    DEFAULT_OUTPUT_NAME,
+};
+use crate::error::IgLiveError;
+use crate::mp4::{init_info, mux_init, retrack_fragments, tracks_info};
+use crate::mpd::MediaType;
+use crate::pts::get_stream_info;
+use crate::store::{SegmentKey, SegmentStore};
+
+/// Name of the growing recording in the download directory
+pub const RECORDING_NAME: &str = "recording.mp4";
+
+/// Name of the file describing the segments in the recording
+const RECORDING_INFO_NAME: &str = "recording.json";
+
+/// Time between updates of the recording's info file while recording
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Contiguous segments of one track appended to the recording
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecordedTrack {
+    representation: String,
+    /// Segment time of the first segment.
+    t_start: usize,
+    /// Segment time of the last segment.
+    t_end: usize,
+    pts_start: usize,
+    pts_end: usize,
+    timescale: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RecordingInfo {
+    video: Option<RecordedTrack>,
+    audio: Option<RecordedTrack>,
+}
+
+impl RecordingInfo {
+    fn track(&mut self, media_type: &MediaType) -> Option<&mut Option<RecordedTrack>> {
+        match media_type {
+            MediaType::Video => Some(&mut self.video),
+            MediaType::Audio => Some(&mut self.audio),
+            MediaType::Unknown => None,
+        }
+    }
+}
+
+enum RecorderMessage {
+    Init(MediaType, Vec<u8>),
+    Segment(SegmentKey, (usize, usize), Vec<u8>),
+}
+
+/// Appends live segments to a fragmented MP4 file of video and audio while downloading, as long
+/// as they follow each other without gaps.
+///
+/// Segments are sent to a task writing the recording, so downloading never waits for the disk.
+pub(crate) struct Recorder {
+    tx: UnboundedSender<RecorderMessage>,
+    task: JoinHandle<()>,
+}
+
+impl Recorder {
+    /// Start recording into `dir`. A recording left by an earlier download is replaced.
+    pub(crate) fn spawn(dir: &Path) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write_recording(dir.to_owned(), rx));
+        Self { tx, task }
+    }
+
+    /// Add a representation's initialization data
+    pub(crate) fn init(&self, media_type: &MediaType, data: &[u8]) {
+        let _ = self
+            .tx
+            .send(RecorderMessage::Init(media_type.clone(), data.to_vec()));
+    }
+
+    /// Append a live segment. Recording stops at the first segment that doesn't continue its
+    /// track, merge picks up the rest from the downloaded segments.
+    pub(crate) fn append(&self, key: &SegmentKey, pts: (usize, usize), data: &[u8]) {
+        let _ = self
+            .tx
+            .send(RecorderMessage::Segment(key.clone(), pts, data.to_vec()));
+    }
+
+    /// Wait for the sent segments to be written
+    pub(crate) async fn finish(self) {
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+}
+
+/// Write the recording until the channel closes or a segment can't be appended, then describe
+/// what was written in the info file
+async fn write_recording(dir: PathBuf, mut rx: UnboundedReceiver<RecorderMessage>) {
+    let mut writer = RecordingWriter {
+        dir,
+        file: None,
+        inits: HashMap::new(),
+        info: RecordingInfo::default(),
+        sequence: 0,
+        checkpoint: Instant::now(),
+    };
+    let _ = tokio::fs::remove_file(writer.dir.join(RECORDING_INFO_NAME)).await;
+
+    while let Some(message) = rx.recv().await {
+        match message {
+            RecorderMessage::Init(media_type, data) => {
+                writer.inits.entry(media_type).or_insert(data);
+            }
+            RecorderMessage::Segment(key, pts, data) => {
+                if let Err(e) = writer.append(&key, pts, &data).await {
+                    eprintln!("WARNING: Stopped recording {RECORDING_NAME}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+    if let Err(e) = writer.write_info().await {
+        eprintln!("WARNING: Failed to write {RECORDING_INFO_NAME}: {e}");
+    }
+}
+
+struct RecordingWriter {
+    dir: PathBuf,
+    file: Option<File>,
+    inits: HashMap<MediaType, Vec<u8>>,
+    info: RecordingInfo,
+    sequence: u32,
+    /// When the info file was last written.
+    checkpoint: Instant,
+}
+
+impl RecordingWriter {
+    async fn append(&mut self, key: &SegmentKey, pts: (usize, usize), data: &[u8]) -> Result<()> {
+        let track_id = match key.media_type {
+            MediaType::Video => 1,
+            MediaType::Audio => 2,
+            MediaType::Unknown => return Ok(()),
+        };
+        let Some(init) = self.inits.get(&key.media_type) else {
+            return Ok(());
+        };
+        let timescale = init_info(init).ok_or(IgLiveError::MissingInit)?.timescale;
+
+        let recorded = match self.info.track(&key.media_type).unwrap() {
+            Some(track) if track.representation != key.representation => {
+                bail!("{:?} representation changed", key.media_type)
+            }
+            Some(track) if track.pts_end != pts.0 => {
+                bail!(
+                    "missing {:?} segment at PTS={}",
+                    key.media_type,
+                    track.pts_end
+                )
+            }
+            Some(track) => RecordedTrack {
+                t_end: key.t,
+                pts_end: pts.1,
+                ..track.clone()
+            },
+            None => RecordedTrack {
+                representation: key.representation.clone(),
+                t_start: key.t,
+                t_end: key.t,
+                pts_start: pts.0,
+                pts_end: pts.1,
+                timescale,
+            },
+        };
+
+        // Start the file once both tracks' initialization data is known
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let (Some(video), Some(audio)) = (
+                    self.inits.get(&MediaType::Video),
+                    self.inits.get(&MediaType::Audio),
+                ) else {
+                    return Ok(());
+                };
+                let init = mux_init(video, audio).ok_or(IgLiveError::MissingInit)?;
+                let mut file = File::create(self.dir.join(RECORDING_NAME)).await?;
+                file.write_all(&init).await?;
+                self.file.insert(file)
+            }
+        };
+        let fragment = retrack_fragments(data, track_id, &mut self.sequence)
+            .ok_or(IgLiveError::CorruptSegment)?;
+        file.write_all(&fragment).await?;
+
+        *self.info.track(&key.media_type).unwrap() = Some(recorded);
+        if self.checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            self.write_info().await?;
+        }
+        Ok(())
+    }
+
+    /// Describe the segments written so far, after making sure they are on disk.
+    /// A crash between checkpoints leaves segments in the recording that the info file doesn't
+    /// cover, so merge falls back to merging all segments.
+    async fn write_info(&mut self) -> Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.sync_data().await?;
+        tokio::fs::write(
+            self.dir.join(RECORDING_INFO_NAME),
+            serde_json::to_vec_pretty(&self.info)?,
+        )
+        .await?;
+        self.checkpoint = Instant::now();
+        Ok(())
+    }
+}
+
+/// Finish merging from the recording written while downloading, by adding the segments
+/// downloaded before it and remuxing to the output format.
+///
+/// Returns `None` if there is no usable recording: when re-encoding, splitting or filling gaps,
+/// for audio-only output, when segments were downloaded after the recording stopped, or when the
+/// segments before the recording don't have the same tracks to be joined without re-muxing.
+pub(super) async fn merge_recording(
+    store: &dyn SegmentStore,
+    dir: &Path,
+    config: &MergeConfig,
+) -> Result<Option<MergeReport>> {
+    if config.fill_gaps.is_some()
+        || config.transcode.is_some()
+        || config.split.is_some()
+        || !config.format.has_video()
+    {
+        return Ok(None);
+    }
+    let Ok(info) = fs::read(dir.join(RECORDING_INFO_NAME)) else {
+        return Ok(None);
+    };
+    let RecordingInfo {
+        video: Some(video),
+        audio: Some(audio),
+    } = serde_json::from_slice(&info)?
+    else {
+        return Ok(None);
+    };
+
+    // Every other segment must come before the recording
+    let mut head = false;
+    for key in store.list_segments().await? {
+        let recorded = match key.media_type {
+            MediaType::Video => &video,
+            MediaType::Audio => &audio,
+            MediaType::Unknown => continue,
+        };
+        if key.representation != recorded.representation || key.t > recorded.t_end {
+            println!("Segments were downloaded outside of {RECORDING_NAME}, merging all segments");
+            return Ok(None);
+        }
+        head |= key.t < recorded.t_start;
+    }
+
+    println!("Merging from {RECORDING_NAME}");
+    let file_name_base = dir.file_name().unwrap().to_string_lossy().to_string();
+    let output_dir = config.output_dir.as_deref().unwrap_or(dir);
+    let output_name = config.output_name.as_deref().unwrap_or(DEFAULT_OUTPUT_NAME);
+    let output_path = output_path(output_name, &file_name_base, config.format, output_dir);
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let recording = dir.join(RECORDING_NAME);
+
+    // Merge the earlier segments up to where the recording starts. Where one track starts later
+    // in the recording, it is left empty for the difference.
+    let head = if head {
+        let head_config = MergeConfig {
+            align: config.align,
+            format: super::OutputFormat::Fmp4,
+            output_dir: Some(output_dir.to_owned()),
+            output_name: Some(file_name_base.clone() + "head.tmp"),
+            ..Default::default()
+        };
+        let end = video.t_start.min(audio.t_start);
+        let report = merge_range(store, dir, &head_config, Some(0..end)).await?;
+        let _ = fs::remove_file(report.output.with_extension("metadata.json"));
+
+        // Joining without re-muxing needs the same tracks in both files
+        if !same_tracks(&report.output, &recording)? {
+            println!(
+                "Earlier segments don't match the tracks of {RECORDING_NAME}, merging all segments"
+            );
+            let _ = fs::remove_file(&report.output);
+            return Ok(None);
+        }
+        Some(report)
+    } else {
+        None
+    };
+
+    let result = async {
+        // Tracks spanning the earlier segments and the recording
+        let mut tracks = vec![];
+        let mut gaps = vec![];
+        for (media_type, recorded) in [(MediaType::Video, &video), (MediaType::Audio, &audio)] {
+            let init = store
+                .get_init(&media_type, &recorded.representation)
+                .await?
+                .ok_or(IgLiveError::MissingInit)?;
+            let timescale = recorded.timescale as f64;
+            let mut track = Track {
+                representation: recorded.representation.clone(),
+                info: get_stream_info(init).await?,
+                start: recorded.pts_start as f64 / timescale,
+                end: recorded.pts_end as f64 / timescale,
+                t_start: recorded.t_start,
+                gaps: vec![],
+            };
+            if let Some(head) = &head {
+                let coverage = match media_type {
+                    MediaType::Video => head.alignment.video.as_ref().unwrap(),
+                    _ => &head.alignment.audio,
+                };
+                if coverage.end + 0.001 < track.start {
+                    eprintln!("WARNING: Missing {media_type:?} segments before {RECORDING_NAME}");
+                }
+                track.start = coverage.start;
+                track.gaps = head
+                    .gaps
+                    .iter()
+                    .filter(|g| g.media_type == media_type)
+                    .cloned()
+                    .collect();
+            }
+            gaps.extend(track.gaps.clone());
+            tracks.push((media_type, track));
+        }
+        let alignment = align(Some(&tracks[0].1), &tracks[1].1, config.align)?;
+        let tracks: Vec<_> = tracks.iter().map(|(m, t)| (m.clone(), t)).collect();
+        let metadata = Metadata::new(store.get_meta().await?, &tracks, &gaps);
+
+        let mut args: Vec<OsString> = vec![];
+        let list_path = output_path.with_extension("txt.tmp");
+        match &head {
+            Some(head) => {
+                fs::write(
+                    &list_path,
+                    concat_list(&[head.output.clone(), recording.clone()])?,
+                )?;
+                args.extend(["-f", "concat", "-safe", "0"].map(OsString::from));
+                args.extend(["-i".into(), list_path.clone().into()]);
+            }
+            None => args.extend(["-i".into(), recording.clone().into()]),
+        }
+        // The earlier segments are already cut to start with both tracks
+        if config.align == Align::Trim {
+            if head.is_none() {
+                args.extend(["-ss".into(), format!("{:.6}", alignment.skip()).into()]);
+            }
+            args.extend(["-t".into(), format!("{:.6}", alignment.duration).into()]);
+        }
+        for (key, value) in metadata.tags() {
+            args.extend(["-metadata".into(), format!("{key}={value}").into()]);
+        }
+        let output = process::Command::new("ffmpeg")
+            .args(args)
+            .args(["-map", "0", "-c", "copy"])
+            .args(config.format.muxer_args())
+            .arg("-y")
+            .arg(&output_path)
+            .output();
+        let _ = fs::remove_file(&list_path);
+        if !output?.status.success() {
+            bail!(IgLiveError::FfmpegFail);
+        }
+        Ok((alignment, gaps, metadata))
+    }
+    .await;
+    if let Some(head) = &head {
+        let _ = fs::remove_file(&head.output);
+    }
+    let (alignment, gaps, metadata) = result?;
+    metadata.write_json(&output_path.with_extension("metadata.json"))?;
+
+    // The recording is no longer needed
+    let _ = fs::remove_file(&recording);
+    let _ = fs::remove_file(dir.join(RECORDING_INFO_NAME));
+
+    print_alignment(&alignment, config.align);
+    println!("Merged video written to {:?}", output_path);
+    Ok(Some(MergeReport {
+        output: output_path,
+        parts: vec![],
+        playlist: None,
+        gaps,
+        alignment,
+        metadata,
+    }))
+}
+
+/// Whether two fragmented MP4 files have tracks with the same IDs, timescales and codecs
+fn same_tracks(a: &Path, b: &Path) -> Result<bool> {
+    let tracks = |path| -> Result<_> {
+        let mut tracks = tracks_info(&read_init(path)?).ok_or(IgLiveError::MissingInit)?;
+        tracks.sort_by_key(|(id, _)| *id);
+        Ok(tracks)
+    };
+    Ok(tracks(a)? == tracks(b)?)
+}
+
+/// Read the initialization data at the start of a fragmented MP4 file
+fn read_init(path: &Path) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let mut init = vec![];
+    loop {
+        let mut header = [0; 8];
+        file.read_exact(&mut header)?;
+        let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let kind = &header[4..];
+        if size < header.len() || kind == b"moof" || kind == b"mdat" {
+            bail!(IgLiveError::MissingInit);
+        }
+        let start = init.len();
+        init.extend_from_slice(&header);
+        init.resize(start + size, 0);
+        file.read_exact(&mut init[start + header.len()..])?;
+        if kind == b"moov" {
+            return Ok(init);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::Result;
+
+use super::OutputFormat;
+use crate::error::IgLiveError;
+
+/// Options for splitting the merged file into parts
+#[derive(Clone, Debug, Default)]
+pub struct SplitConfig {
+    /// Maximum length of a part in seconds.
+    pub max_duration: Option<f64>,
+
+    /// Maximum size of a part in bytes, estimated from the segment sizes before any transcoding.
+    pub max_size: Option<u64>,
+
+    /// Also write an `.m3u8` playlist listing the parts.
+    pub playlist: bool,
+}
+
+/// Times in seconds to start new parts at.
+///
+/// # Arguments
+///
+/// `units` - Start time in seconds and size in bytes of each segment, the first starting at 0.
+/// `duration` - Duration of the whole file in seconds.
+pub(super) fn split_points(units: &[(f64, u64)], duration: f64, config: &SplitConfig) -> Vec<f64> {
+    let mut points = vec![];
+    let (mut part_start, mut part_bytes) = (0.0, 0);
+    for (i, &(start, bytes)) in units.iter().enumerate() {
+        let end = units.get(i + 1).map_or(duration, |u| u.0);
+        let too_long = config.max_duration.is_some_and(|d| end - part_start > d);
+        let too_big = config.max_size.is_some_and(|s| part_bytes + bytes > s);
+        if (too_long || too_big) && start > part_start {
+            points.push(start);
+            part_start = start;
+            part_bytes = 0;
+        }
+        part_bytes += bytes;
+    }
+    points
+}
+
+/// Name template for parts, adding a `{part}` placeholder before the extension if missing
+pub(super) fn part_template(template: &str) -> String {
+    if template.contains("{part}") {
+        template.to_owned()
+    } else if let Some(stem) = template.strip_suffix(".{ext}") {
+        format!("{stem}-{{part}}.{{ext}}")
+    } else {
+        format!("{template}-{{part}}")
+    }
+}
+
+/// Split `input` at `points` without re-encoding and return the paths of the parts.
+///
+/// # Arguments
+///
+/// `pattern` - Path of the parts with a `{part}` placeholder for the part number.
+/// `playlist` - Path to write an `.m3u8` playlist of the parts to, if any.
+pub(super) fn split_file(
+    input: &Path,
+    points: &[f64],
+    format: OutputFormat,
+    pattern: &Path,
+    playlist: Option<&Path>,
+) -> Result<Vec<PathBuf>> {
+    let pattern = pattern.to_string_lossy();
+    let times: Vec<_> = points.iter().map(|p| format!("{p:.6}")).collect();
+
+    let mut cmd = process::Command::new("ffmpeg");
+    cmd.args([OsStr::new("-i"), input.as_os_str()])
+        .args(["-map", "0", "-c", "copy", "-f", "segment"])
+        .args(["-reset_timestamps", "1", "-segment_start_number", "1"])
+        .args(format.segment_args());
+    if !times.is_empty() {
+        cmd.args(["-segment_times", &times.join(",")]);
+    }
+    if let Some(playlist) = playlist {
+        cmd.args([OsStr::new("-segment_list"), playlist.as_os_str()])
+            .args(["-segment_list_type", "m3u8"]);
+    }
+    let output = cmd
+        .arg("-y")
+        .arg(pattern.replace('%', "%%").replace("{part}", "%03d"))
+        .output()?;
+    if !output.status.success() {
+        return Err(IgLiveError::FfmpegFail.into());
+    }
+
+    Ok((1..=points.len() + 1)
+        .map(|n| PathBuf::from(pattern.replace("{part}", &format!("{n:03}"))))
+        .filter(|p| p.exists())
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_by_duration_and_size() {
+        let units: Vec<_> = (0..10).map(|i| (i as f64 * 2.0, 100)).collect();
+
+        let by_duration = SplitConfig {
+            max_duration: Some(5.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            split_points(&units, 20.0, &by_duration),
+            [4.0, 8.0, 12.0, 16.0]
+        );
+
+        let by_size = SplitConfig {
+            max_size: Some(350),
+            ..Default::default()
+        };
+        assert_eq!(split_points(&units, 20.0, &by_size), [6.0, 12.0, 18.0]);
+
+        // A segment larger than the limit still gets its own part
+        let tiny = SplitConfig {
+            max_size: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(split_points(&units[..3], 6.0, &tiny), [2.0, 4.0]);
+
+        assert_eq!(part_template("{name}.{ext}"), "{name}-{part}.{ext}");
+        assert_eq!(part_template("{part}-{name}.{ext}"), "{part}-{name}.{ext}");
+    }
+}
@@ -0,0 +1,257 @@
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+use crate::error::IgLiveError;
+
+/// Video encoder used when transcoding
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    /// H.264 with libx264.
+    H264,
+    /// H.265 with libx265.
+    H265,
+    /// AV1 with libsvtav1.
+    Av1,
+}
+
+/// Encoding settings for [transcoding][super::MergeConfig::transcode] the merged file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct TranscodeProfile {
+    /// Video encoder.
+    pub codec: VideoCodec,
+
+    /// Constant rate factor, lower is better quality.
+    pub crf: u8,
+
+    /// Target video bitrate in kbit/s, used instead of `crf` for two-pass encoding.
+    pub video_bitrate: Option<u32>,
+
+    /// AAC audio bitrate in kbit/s.
+    pub audio_bitrate: u32,
+
+    /// Maximum length in pixels of the longer side of the video, larger video is scaled down.
+    pub max_size: Option<u32>,
+
+    /// Encode in two passes, requires `video_bitrate`.
+    pub two_pass: bool,
+}
+
+const fn profile(
+    codec: VideoCodec,
+    crf: u8,
+    audio_bitrate: u32,
+    max_size: Option<u32>,
+) -> TranscodeProfile {
+    TranscodeProfile {
+        codec,
+        crf,
+        video_bitrate: None,
+        audio_bitrate,
+        max_size,
+        two_pass: false,
+    }
+}
+
+/// Named transcode profiles
+pub const PROFILES: [(&str, TranscodeProfile); 6] = [
+    ("h264", profile(VideoCodec::H264, 23, 128, None)),
+    ("h264-720p", profile(VideoCodec::H264, 23, 128, Some(1280))),
+    ("h265", profile(VideoCodec::H265, 26, 96, None)),
+    ("h265-720p", profile(VideoCodec::H265, 26, 96, Some(1280))),
+    ("av1", profile(VideoCodec::Av1, 32, 96, None)),
+    ("av1-720p", profile(VideoCodec::Av1, 32, 96, Some(1280))),
+];
+
+impl TranscodeProfile {
+    /// Look up a profile in [PROFILES] by name
+    pub fn named(name: &str) -> Option<Self> {
+        PROFILES.iter().find(|(n, _)| *n == name).map(|(_, p)| *p)
+    }
+
+    /// ffmpeg output options for video, `pass` is 1 or 2 for two-pass encoding
+    fn video_args(&self, pass: Option<(u8, &Path)>) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![];
+        let mut push = |a: &[&str]| args.extend(a.iter().map(OsString::from));
+        match self.codec {
+            VideoCodec::H264 => push(&["-c:v", "libx264", "-preset", "medium"]),
+            VideoCodec::H265 => push(&["-c:v", "libx265", "-preset", "medium", "-tag:v", "hvc1"]),
+            VideoCodec::Av1 => push(&["-c:v", "libsvtav1", "-preset", "8"]),
+        }
+        push(&["-pix_fmt", "yuv420p"]);
+        if let Some(max) = self.max_size {
+            push(&[
+                "-vf",
+                &format!(
+                    "scale='if(gte(iw,ih),min(iw,{max}),-2)':'if(gte(iw,ih),-2,min(ih,{max}))'"
+                ),
+            ]);
+        }
+
+        match (self.video_bitrate, pass) {
+            (Some(bitrate), Some((pass, log))) => {
+                push(&["-b:v", &format!("{bitrate}k")]);
+                if self.codec == VideoCodec::H265 {
+                    let stats = log.to_string_lossy().replace(':', "\\:");
+                    push(&["-x265-params", &format!("pass={pass}:stats={stats}")]);
+                } else {
+                    push(&["-pass", &pass.to_string()]);
+                    args.push("-passlogfile".into());
+                    args.push(log.into());
+                }
+            }
+            _ => push(&["-crf", &self.crf.to_string()]),
+        }
+        args
+    }
+
+    /// ffmpeg output options for audio
+    fn audio_args(&self) -> Vec<OsString> {
+        ["-c:a", "aac", "-b:a", &format!("{}k", self.audio_bitrate)]
+            .iter()
+            .map(OsString::from)
+            .collect()
+    }
+}
+
+/// Encode `inputs` into `output` with `profile`, showing progress.
+///
+/// # Arguments
+///
+/// `inputs` - ffmpeg input options and inputs, including any trimming.
+/// `has_video` - Whether the output contains video.
+/// `duration` - Expected output duration in seconds, used for progress.
+/// `muxer_args` - ffmpeg output options selecting the container.
+pub(super) async fn transcode(
+    inputs: &[OsString],
+    profile: &TranscodeProfile,
+    has_video: bool,
+    duration: f64,
+    muxer_args: &[&str],
+    output: &Path,
+) -> Result<()> {
+    if !has_video {
+        let args = [inputs, &profile.audio_args(), &os_args(muxer_args)].concat();
+        return run_with_progress("Transcode", &args, output, duration).await;
+    }
+    if profile.two_pass && profile.video_bitrate.is_none() {
+        bail!("Two-pass transcoding needs a video bitrate");
+    }
+    if !profile.two_pass {
+        let args = [
+            inputs,
+            &profile.video_args(None),
+            &profile.audio_args(),
+            &os_args(muxer_args),
+        ]
+        .concat();
+        return run_with_progress("Transcode", &args, output, duration).await;
+    }
+
+    // First pass only analyzes video, second pass writes the output
+    let log = output.with_extension("passlog");
+    let first = [
+        inputs,
+        &profile.video_args(Some((1, &log))),
+        &os_args(&["-an", "-f", "null"]),
+    ]
+    .concat();
+    let second = [
+        inputs,
+        &profile.video_args(Some((2, &log))),
+        &profile.audio_args(),
+        &os_args(muxer_args),
+    ]
+    .concat();
+    let mut result = run_with_progress("Pass 1/2", &first, Path::new("-"), duration).await;
+    if result.is_ok() {
+        result = run_with_progress("Pass 2/2", &second, output, duration).await;
+    }
+
+    // Remove pass logs, named differently by each encoder
+    let dir = log
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = log.file_name().unwrap_or_default().to_string_lossy();
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(name.as_ref())
+        {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    result
+}
+
+/// Run ffmpeg with `args`, writing to `output` and showing progress through `duration` seconds
+async fn run_with_progress(
+    prefix: &str,
+    args: &[OsString],
+    output: &Path,
+    duration: f64,
+) -> Result<()> {
+    let pb = ProgressBar::new((duration * 1000.0) as u64);
+    pb.set_style(ProgressStyle::with_template(
+        "{prefix:.bold.fg.green} [{bar:40}] {percent}% {elapsed_precise} ETA {eta}",
+    )?);
+    pb.set_prefix(prefix.to_owned());
+    pb.enable_steady_tick(Duration::from_millis(500));
+
+    let mut child = Command::new("ffmpeg")
+        .args(args)
+        .args(["-progress", "pipe:1", "-nostats", "-y"])
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(us) = line.strip_prefix("out_time_us=") {
+            if let Ok(us) = us.trim().parse::<u64>() {
+                pb.set_position(us / 1000);
+            }
+        }
+    }
+    let status = child.wait().await?;
+    pb.finish();
+
+    if !status.success() {
+        return Err(IgLiveError::FfmpegFail.into());
+    }
+    Ok(())
+}
+
+fn os_args(args: &[&str]) -> Vec<OsString> {
+    args.iter().map(OsStr::new).map(OsString::from).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn two_pass_args() {
+        let mut profile = TranscodeProfile::named("h265-720p").unwrap();
+        let crf = profile.video_args(None);
+        assert!(crf.iter().any(|a| a == "-crf"));
+
+        profile.two_pass = true;
+        profile.video_bitrate = Some(1500);
+        let pass = profile.video_args(Some((2, Path::new("out.passlog"))));
+        assert!(pass.iter().any(|a| a == "1500k"));
+        assert!(pass.iter().any(|a| a == "pass=2:stats=out.passlog"));
+        assert!(!pass.iter().any(|a| a == "-crf"));
+    }
+}
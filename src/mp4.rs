@@ -0,0 +1,396 @@
+use std::ops::Range;
+
+/// Top level box of an MP4 file
+pub struct Mp4Box {
+    pub kind: [u8; 4],
+    pub range: Range<usize>,
+    /// Length of the box header
+    pub header: usize,
+}
+
+/// Split `data` into its top level boxes.
+/// Returns `None` if a box header is malformed or a box extends past the end of `data`.
+pub fn boxes(data: &[u8]) -> Option<Vec<Mp4Box>> {
+    let mut ret = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8)?;
+        let kind = header[4..8].try_into().unwrap();
+        let (size, header) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+            0 => (data.len() - pos, 8),
+            1 => (
+                u64::from_be_bytes(data.get(pos + 8..pos + 16)?.try_into().unwrap()) as usize,
+                16,
+            ),
+            s => (s as usize, 8),
+        };
+        if size < header || pos + size > data.len() {
+            return None;
+        }
+        ret.push(Mp4Box {
+            kind,
+            range: pos..pos + size,
+            header,
+        });
+        pos += size;
+    }
+    Some(ret)
+}
+
+/// Split a segment that starts with initialization data into initialization data and fragment.
+/// Returns `None` if the segment has no `moov` box.
+pub fn split_init(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let moov = boxes(data)?.into_iter().find(|b| &b.kind == b"moov")?;
+    Some(data.split_at(moov.range.end))
+}
+
+/// Get the contents of the first box found by following `path` through nested boxes
+pub fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    Some(&data[box_range(data, path)?])
+}
+
+/// Range of the contents of the first box found by following `path` through nested boxes
+fn box_range(data: &[u8], path: &[&[u8; 4]]) -> Option<Range<usize>> {
+    let Some((kind, rest)) = path.split_first() else {
+        return Some(0..data.len());
+    };
+    let b = boxes(data)?.into_iter().find(|b| &&b.kind == kind)?;
+    let start = b.range.start + b.header;
+    let inner = box_range(&data[start..b.range.end], rest)?;
+    Some(start + inner.start..start + inner.end)
+}
+
+/// Build a box with a 32-bit size
+fn make_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut ret = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    ret.extend_from_slice(kind);
+    ret.extend_from_slice(payload);
+    ret
+}
+
+/// Overwrite a big-endian 32-bit integer
+fn set_u32(data: &mut [u8], offset: usize, value: u32) -> Option<()> {
+    data.get_mut(offset..offset + 4)?
+        .copy_from_slice(&value.to_be_bytes());
+    Some(())
+}
+
+/// Combine the initialization data of a video track and an audio track into initialization data
+/// of one movie, with track IDs 1 and 2.
+/// Returns `None` if either has no track.
+pub fn mux_init(video: &[u8], audio: &[u8]) -> Option<Vec<u8>> {
+    // next_track_ID is the last field of mvhd
+    let mut mvhd = find_box(video, &[b"moov", b"mvhd"])?.to_vec();
+    let next_track_id = mvhd.len().checked_sub(4)?;
+    set_u32(&mut mvhd, next_track_id, 3)?;
+    let mut moov = make_box(b"mvhd", &mvhd);
+
+    let mut mvex = vec![];
+    for (id, init) in [(1, video), (2, audio)] {
+        let mut trak = find_box(init, &[b"moov", b"trak"])?.to_vec();
+        let tkhd = box_range(&trak, &[b"tkhd"])?;
+        let offset = if trak[tkhd.start] == 1 { 20 } else { 12 };
+        set_u32(&mut trak, tkhd.start + offset, id)?;
+        moov.extend(make_box(b"trak", &trak));
+
+        let mut trex = find_box(init, &[b"moov", b"mvex", b"trex"])?.to_vec();
+        set_u32(&mut trex, 4, id)?;
+        mvex.extend(make_box(b"trex", &trex));
+    }
+    moov.extend(make_box(b"mvex", &mvex));
+
+    let ftyp = find_box(video, &[b"ftyp"]).map_or(vec![], |f| make_box(b"ftyp", f));
+    Some([ftyp, make_box(b"moov", &moov)].concat())
+}
+
+/// Set the track ID of every fragment in `data` and number them consecutively after `sequence`,
+/// which is updated to the last number used.
+/// Returns `None` if a fragment is malformed.
+pub fn retrack_fragments(data: &[u8], track_id: u32, sequence: &mut u32) -> Option<Vec<u8>> {
+    let mut data = data.to_vec();
+    for moof in boxes(&data)?.into_iter().filter(|b| &b.kind == b"moof") {
+        let start = moof.range.start + moof.header;
+        let moof_data = &data[start..moof.range.end];
+        let mfhd = box_range(moof_data, &[b"mfhd"])?;
+        let tfhd = box_range(moof_data, &[b"traf", b"tfhd"])?;
+
+        *sequence += 1;
+        set_u32(&mut data, start + mfhd.start + 4, *sequence)?;
+        set_u32(&mut data, start + tfhd.start + 4, track_id)?;
+    }
+    Some(data)
+}
+
+/// Track parameters from initialization data
+#[derive(Debug, PartialEq)]
+pub struct InitInfo {
+    /// PTS units per second.
+    pub timescale: u32,
+    /// Codec as an RFC 6381 `codecs` parameter.
+    pub codec: String,
+    /// Video width in pixels.
+    pub width: Option<u16>,
+    /// Video height in pixels.
+    pub height: Option<u16>,
+}
+
+/// Read the timescale, codec and dimensions of the first track in initialization data.
+/// Returns `None` if the track can't be found.
+pub fn init_info(init: &[u8]) -> Option<InitInfo> {
+    trak_info(find_box(init, &[b"moov", b"trak"])?)
+}
+
+/// Read the track ID and parameters of every track in initialization data.
+/// Returns `None` if a track is malformed.
+pub fn tracks_info(init: &[u8]) -> Option<Vec<(u32, InitInfo)>> {
+    let moov = find_box(init, &[b"moov"])?;
+    boxes(moov)?
+        .into_iter()
+        .filter(|b| &b.kind == b"trak")
+        .map(|b| {
+            let trak = &moov[b.range.start + b.header..b.range.end];
+            let tkhd = find_box(trak, &[b"tkhd"])?;
+            let offset = if tkhd.first()? == &1 { 20 } else { 12 };
+            let id = u32::from_be_bytes(tkhd.get(offset..offset + 4)?.try_into().unwrap());
+            Some((id, trak_info(trak)?))
+        })
+        .collect()
+}
+
+/// Read the timescale, codec and dimensions of a track from the contents of its `trak` box
+fn trak_info(trak: &[u8]) -> Option<InitInfo> {
+    let mdia = find_box(trak, &[b"mdia"])?;
+
+    // Version 1 has 64-bit creation and modification times
+    let mdhd = find_box(mdia, &[b"mdhd"])?;
+    let offset = if mdhd.first()? == &1 { 20 } else { 12 };
+    let timescale = u32::from_be_bytes(mdhd.get(offset..offset + 4)?.try_into().unwrap());
+
+    // Skip the version, flags and entry count of stsd
+    let stsd = find_box(mdia, &[b"minf", b"stbl", b"stsd"])?;
+    let stsd = stsd.get(8..)?;
+    let entry = boxes(stsd)?.into_iter().next()?;
+    let fourcc = String::from_utf8_lossy(&entry.kind).to_string();
+    let entry = &stsd[entry.range.start + entry.header..entry.range.end];
+
+    let dimension = |i: usize| Some(u16::from_be_bytes(entry.get(i..i + 2)?.try_into().unwrap()));
+    let info = match fourcc.as_str() {
+        "avc1" | "avc3" => {
+            let codec = entry
+                .get(78..)
+                .and_then(|c| find_box(c, &[b"avcC"]))
+                .and_then(|c| c.get(1..4))
+                .map_or(fourcc.clone(), |p| {
+                    format!("{fourcc}.{:02x}{:02x}{:02x}", p[0], p[1], p[2])
+                });
+            InitInfo {
+                timescale,
+                codec,
+                width: dimension(24),
+                height: dimension(26),
+            }
+        }
+        "mp4a" => InitInfo {
+            timescale,
+            codec: entry
+                .get(28..)
+                .and_then(|c| find_box(c, &[b"esds"]))
+                .and_then(aac_codec)
+                .unwrap_or_else(|| "mp4a.40.2".to_owned()),
+            width: None,
+            height: None,
+        },
+        _ => InitInfo {
+            timescale,
+            width: dimension(24).filter(|_| entry.len() >= 78),
+            height: dimension(26).filter(|_| entry.len() >= 78),
+            codec: fourcc,
+        },
+    };
+    Some(info)
+}
+
+/// Build `mp4a.<object type>.<audio object type>` from the contents of an `esds` box
+fn aac_codec(esds: &[u8]) -> Option<String> {
+    // Skip the version and flags, then walk the descriptors
+    let mut data = esds.get(4..)?;
+    let mut object_type = None;
+    loop {
+        let tag = *data.first()?;
+        let mut len = 0;
+        let mut i = 1;
+        loop {
+            let b = *data.get(i)?;
+            len = (len << 7) | (b & 0x7f) as usize;
+            i += 1;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let body = data.get(i..i + len)?;
+        data = match tag {
+            // ES_Descriptor, skip its ID, flags and optional fields
+            3 => {
+                let flags = *body.get(2)?;
+                let mut skip = 3;
+                if flags & 0x80 != 0 {
+                    skip += 2;
+                }
+                if flags & 0x40 != 0 {
+                    skip += 1 + *body.get(skip)? as usize;
+                }
+                if flags & 0x20 != 0 {
+                    skip += 2;
+                }
+                body.get(skip..)?
+            }
+            // DecoderConfigDescriptor
+            4 => {
+                object_type = Some(*body.first()?);
+                body.get(13..)?
+            }
+            // DecoderSpecificInfo, starting with the audio object type
+            5 => {
+                let aot = body.first()? >> 3;
+                return Some(format!("mp4a.{:02x}.{aot}", object_type?));
+            }
+            _ => return None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split() {
+        let init = [make_box(b"ftyp", b"iso5"), make_box(b"moov", &[0; 16])].concat();
+        let fragment = [make_box(b"moof", &[1; 8]), make_box(b"mdat", &[2; 32])].concat();
+        let segment = [init.clone(), fragment.clone()].concat();
+
+        assert_eq!(
+            split_init(&segment),
+            Some((init.as_slice(), fragment.as_slice()))
+        );
+        assert_eq!(split_init(&fragment), None);
+        assert!(boxes(&segment[..segment.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn init_info_from_boxes() {
+        let mut mdhd = vec![0; 12];
+        mdhd.extend_from_slice(&48000u32.to_be_bytes());
+        mdhd.extend_from_slice(&[0; 8]);
+        let esds = [
+            vec![0; 4],
+            vec![3, 22, 0, 1, 0],
+            vec![4, 17, 0x40, 0x15],
+            vec![0; 11],
+            vec![5, 2, 0x11, 0x90],
+        ]
+        .concat();
+        let mp4a = [vec![0; 28], make_box(b"esds", &esds)].concat();
+        let stsd = [vec![0, 0, 0, 0, 0, 0, 0, 1], make_box(b"mp4a", &mp4a)].concat();
+        let stbl = make_box(b"stbl", &make_box(b"stsd", &stsd));
+        let mdia = [make_box(b"mdhd", &mdhd), make_box(b"minf", &stbl)].concat();
+        let init = [
+            make_box(b"ftyp", b"iso5"),
+            make_box(b"moov", &make_box(b"trak", &make_box(b"mdia", &mdia))),
+        ]
+        .concat();
+
+        assert_eq!(
+            init_info(&init),
+            Some(InitInfo {
+                timescale: 48000,
+                codec: "mp4a.40.2".to_owned(),
+                width: None,
+                height: None,
+            })
+        );
+    }
+
+    #[test]
+    fn mux_tracks() {
+        let init = |handler: &[u8; 4]| {
+            let tkhd = [vec![0; 12], 1u32.to_be_bytes().to_vec(), vec![0; 4]].concat();
+            let trak = [make_box(b"tkhd", &tkhd), make_box(b"hdlr", handler)].concat();
+            let trex = [vec![0; 4], 1u32.to_be_bytes().to_vec(), vec![0; 16]].concat();
+            let moov = [
+                make_box(b"mvhd", &[0; 100]),
+                make_box(b"trak", &trak),
+                make_box(b"mvex", &make_box(b"trex", &trex)),
+            ]
+            .concat();
+            [make_box(b"ftyp", b"iso5"), make_box(b"moov", &moov)].concat()
+        };
+        let muxed = mux_init(&init(b"vide"), &init(b"soun")).unwrap();
+
+        let moov = find_box(&muxed, &[b"moov"]).unwrap();
+        let traks: Vec<_> = boxes(moov)
+            .unwrap()
+            .into_iter()
+            .filter(|b| &b.kind == b"trak")
+            .map(|b| &moov[b.range.start + 8..b.range.end])
+            .collect();
+        assert_eq!(traks.len(), 2);
+        assert_eq!(find_box(traks[1], &[b"hdlr"]), Some(&b"soun"[..]));
+        assert_eq!(
+            find_box(traks[1], &[b"tkhd"]).unwrap()[12..16],
+            [0, 0, 0, 2]
+        );
+        assert_eq!(find_box(moov, &[b"mvhd"]).unwrap()[96..], [0, 0, 0, 3]);
+
+        let moof = [
+            make_box(b"mfhd", &[0, 0, 0, 0, 0, 0, 0, 9]),
+            make_box(b"traf", &make_box(b"tfhd", &[0, 0, 0, 0, 0, 0, 0, 1])),
+        ]
+        .concat();
+        let fragment = [make_box(b"moof", &moof), make_box(b"mdat", &[7; 4])].concat();
+        let mut sequence = 4;
+        let fragment = retrack_fragments(&fragment, 2, &mut sequence).unwrap();
+        assert_eq!(sequence, 5);
+        assert_eq!(
+            find_box(&fragment, &[b"moof", b"mfhd"]).unwrap()[4..],
+            [0, 0, 0, 5]
+        );
+        assert_eq!(
+            find_box(&fragment, &[b"moof", b"traf", b"tfhd"]).unwrap()[4..],
+            [0, 0, 0, 2]
+        );
+    }
+
+    #[test]
+    fn muxed_tracks_info() {
+        let init = |timescale: u32, entry: &[u8; 4]| {
+            let tkhd = [vec![0; 12], 1u32.to_be_bytes().to_vec(), vec![0; 4]].concat();
+            let mdhd = [vec![0; 12], timescale.to_be_bytes().to_vec(), vec![0; 8]].concat();
+            let stsd = [vec![0, 0, 0, 0, 0, 0, 0, 1], make_box(entry, &[0; 28])].concat();
+            let stbl = make_box(b"stbl", &make_box(b"stsd", &stsd));
+            let mdia = [make_box(b"mdhd", &mdhd), make_box(b"minf", &stbl)].concat();
+            let trak = [make_box(b"tkhd", &tkhd), make_box(b"mdia", &mdia)].concat();
+            let trex = [vec![0; 4], 1u32.to_be_bytes().to_vec(), vec![0; 16]].concat();
+            let moov = [
+                make_box(b"mvhd", &[0; 100]),
+                make_box(b"trak", &trak),
+                make_box(b"mvex", &make_box(b"trex", &trex)),
+            ]
+            .concat();
+            [make_box(b"ftyp", b"iso5"), make_box(b"moov", &moov)].concat()
+        };
+        let muxed = mux_init(&init(90000, b"hvc1"), &init(48000, b"mp4a")).unwrap();
+
+        let tracks = tracks_info(&muxed).unwrap();
+        let ids: Vec<_> = tracks.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(tracks[0].1.timescale, 90000);
+        assert_eq!(tracks[0].1.codec, "hvc1");
+        assert_eq!(tracks[1].1.timescale, 48000);
+        assert_eq!(tracks[1].1.codec, "mp4a.40.2");
+        assert_eq!(
+            init_info(&muxed),
+            Some(tracks.into_iter().next().unwrap().1)
+        );
+    }
+}
@@ -1,7 +1,12 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::Result;
 use reqwest::header::HeaderName;
-use reqwest::{Client, Url};
-use serde::Deserialize;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::error::IgLiveError;
+use crate::fetch::Fetcher;
 
 #[derive(Deserialize, Debug)]
 pub struct Mpd {
@@ -36,16 +41,12 @@ struct AdaptationSet {
 
 #[derive(Deserialize, Debug)]
 pub struct Representation {
+    #[serde(rename = "@id", default)]
+    pub id: String,
     #[serde(rename = "SegmentTemplate")]
     pub segment_template: SegmentTemplate,
     #[serde(rename = "@mimeType")]
     pub mime_type: String,
-    #[serde(rename = "@width")]
-    pub width: Option<usize>,
-    #[serde(rename = "@height")]
-    pub height: Option<usize>,
-    #[serde(rename = "@frameRate")]
-    pub frame_rate: Option<usize>,
     #[serde(rename = "@bandwidth")]
     pub bandwidth: usize,
 }
@@ -75,14 +76,21 @@ pub struct Segment {
 }
 
 impl Mpd {
-    pub async fn download_from_url(client: &Client, url: impl AsRef<str>) -> Result<Self> {
-        let resp = client.get(url.as_ref()).send().await?;
-        let headers = resp.headers().clone();
-        let text = resp.text().await?;
+    pub async fn download_from_url(fetcher: &Fetcher, url: &Url) -> Result<Self> {
+        let resp = fetcher.get(url).await?;
+        if !resp.status.is_success() {
+            return Err(
+                IgLiveError::StatusError(resp.status.into(), url.as_str().to_owned()).into(),
+            );
+        }
+        let text = String::from_utf8_lossy(&resp.body);
 
         let mut manifest: Self = quick_xml::de::from_str(&text)?;
 
-        if let Some(v) = headers.get(HeaderName::from_static("x-fb-video-broadcast-ended")) {
+        if let Some(v) = resp
+            .headers
+            .get(HeaderName::from_static("x-fb-video-broadcast-ended"))
+        {
             if v.to_str()? == "1" {
                 manifest.finished = true;
             }
@@ -91,6 +99,29 @@ impl Mpd {
         Ok(manifest)
     }
 
+    /// Estimate the Unix time in seconds the broadcast started at from the latest segment listed.
+    /// Segment times are in milliseconds.
+    pub fn broadcast_start(&self) -> f64 {
+        let live_edge = self.live_edge();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        now - live_edge.saturating_sub(self.start_frame) as f64 / 1000.0
+    }
+
+    /// Segment time at the end of the latest segment listed
+    pub fn live_edge(&self) -> usize {
+        self.period
+            .adaptation_sets
+            .iter()
+            .flat_map(|a| &a.representations)
+            .flat_map(|r| &r.segment_template.segment_timeline.segments)
+            .map(|s| s.t + s.d)
+            .max()
+            .unwrap_or(self.start_frame)
+    }
+
     pub fn best_media(&self) -> (&Representation, &Representation) {
         let mut cur_video_bandwidth = 0;
         let mut cur_audio_bandwidth = 0;
@@ -111,10 +142,15 @@ impl Mpd {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+/// Type of a representation's media
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MediaType {
+    /// Video track
     Video,
+    /// Audio track
     Audio,
+    /// Anything else
     Unknown,
 }
 
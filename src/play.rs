@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use reqwest::IntoUrl;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::download::{download_with, DownloadConfig, DownloadContext, DownloadEvent};
+use crate::export::{
+    master_playlist, mpd, playlist_name, ExportTrack, LivePlaylist, Package, MASTER_PLAYLIST_NAME,
+    MPD_NAME,
+};
+use crate::store::{MediaType, SegmentStore};
+
+const HLS_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+/// Options for play
+#[derive(Clone, Debug)]
+pub struct PlayConfig {
+    /// Address to listen on.
+    pub addr: SocketAddr,
+}
+
+struct Player {
+    dir: PathBuf,
+    /// Segments are still being added.
+    live: AtomicBool,
+    package: Mutex<Package>,
+    published: Mutex<Published>,
+}
+
+/// State of the manifests already served, which later responses only extend
+#[derive(Default)]
+struct Published {
+    playlists: HashMap<MediaType, LivePlaylist>,
+    /// Wall-clock time of PTS 0 in the dynamic DASH manifest.
+    availability_start: Option<DateTime<Utc>>,
+}
+
+/// Serve a live stream downloaded by [download][crate::download::download] over HTTP, to watch
+/// it in a DASH or HLS player without merging.
+///
+/// | Request               | Response                                 |
+/// |-----------------------|------------------------------------------|
+/// | `GET /master.m3u8`    | HLS master playlist                      |
+/// | `GET /video.m3u8`     | HLS media playlist of the video track    |
+/// | `GET /audio.m3u8`     | HLS media playlist of the audio track    |
+/// | `GET /manifest.mpd`   | DASH manifest                            |
+/// | `GET /segments/<file>`| Initialization data or media segment     |
+///
+/// Manifests are built from the directory for every request. Runs until stopped.
+///
+/// # Arguments
+///
+/// `dir` - Directory containing downloaded video and audio segments.
+pub async fn play(dir: impl AsRef<Path>, config: &PlayConfig) -> Result<()> {
+    Player::new(dir.as_ref(), false).serve(config.addr).await
+}
+
+/// Download an IG live stream while serving it as in [play].
+///
+/// While downloading, the HLS playlists are left open and the DASH manifest is dynamic, so players
+/// keep reloading them and pick up new live segments, and past segments as they are found.
+/// Past segments are appended to the HLS playlists after a discontinuity, since players don't
+/// expect segments to be inserted before ones already listed.
+/// Keeps serving after the download finishes, until stopped.
+///
+/// # Arguments
+///
+/// * `mpd_url` - Full URL of live stream's .mpd manifest.
+/// * `download` - Download options, segments must be stored in the download directory.
+pub async fn play_download(
+    mpd_url: impl IntoUrl,
+    mut download: DownloadConfig,
+    config: &PlayConfig,
+) -> Result<()> {
+    if download.store.is_some() {
+        bail!("Only downloads stored in a directory can be played");
+    }
+
+    // Forward events, waiting for the download directory
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let forward = download.events.replace(tx);
+    let mpd_url = mpd_url.into_url()?;
+    let ctx = DownloadContext::new()?;
+    let mut download = tokio::spawn(async move { download_with(&ctx, mpd_url, download).await });
+    let dir = loop {
+        let Some(event) = rx.recv().await else {
+            // Failed before starting
+            download.await??;
+            bail!("Download finished without starting");
+        };
+        if let Some(tx) = &forward {
+            let _ = tx.send(event.clone());
+        }
+        if let DownloadEvent::Started { dir, .. } = event {
+            break dir;
+        }
+    };
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if let Some(tx) = &forward {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    let player = Player::new(&dir, true);
+    let mut server = tokio::spawn(player.clone().serve(config.addr));
+    tokio::select! {
+        result = &mut server => return result?,
+        result = &mut download => {
+            result??;
+        }
+    }
+    player.live.store(false, Ordering::Relaxed);
+    println!("Download finished, still serving until stopped");
+    server.await?
+}
+
+impl Player {
+    fn new(dir: &Path, live: bool) -> Arc<Self> {
+        Arc::new(Self {
+            dir: dir.to_owned(),
+            live: AtomicBool::new(live),
+            package: Mutex::new(Package::new(dir, None)),
+            published: Mutex::new(Published::default()),
+        })
+    }
+
+    async fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<()> {
+        println!(
+            "Serving {} at http://{addr}/{MASTER_PLAYLIST_NAME} and http://{addr}/{MPD_NAME}",
+            self.dir.display()
+        );
+        let make_svc = make_service_fn(move |_| {
+            let player = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let player = player.clone();
+                    async move { Ok::<_, Infallible>(player.handle(req).await) }
+                }))
+            }
+        });
+        Server::bind(&addr).serve(make_svc).await?;
+
+        Ok(())
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let result = match req.method() {
+            &Method::GET => self.get(req.uri().path().trim_start_matches('/')).await,
+            _ => Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "Method not allowed".to_owned(),
+            )),
+        };
+
+        let (status, content_type, body) = match result {
+            Ok((content_type, body)) => (StatusCode::OK, content_type, body),
+            Err((status, e)) => (status, "text/plain", e.into_bytes()),
+        };
+        Response::builder()
+            .status(status)
+            .header("content-type", content_type)
+            .header("cache-control", "no-cache")
+            .header("access-control-allow-origin", "*")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn get(&self, path: &str) -> Result<(&'static str, Vec<u8>), PlayError> {
+        let not_found = || (StatusCode::NOT_FOUND, "Not found".to_owned());
+
+        // Files in the segments folder
+        if let Some(name) = path.strip_prefix("segments/") {
+            if name.contains('/') || name.starts_with('.') {
+                return Err(not_found());
+            }
+            let content_type = if name.ends_with(".m4a") {
+                "audio/mp4"
+            } else {
+                "video/mp4"
+            };
+            let data = tokio::fs::read(self.dir.join("segments").join(name))
+                .await
+                .map_err(|_| not_found())?;
+            return Ok((content_type, data));
+        }
+
+        let playlists = [MediaType::Video, MediaType::Audio].map(|m| playlist_name(&m));
+        if path != MPD_NAME && path != MASTER_PLAYLIST_NAME && !playlists.contains(&path) {
+            return Err(not_found());
+        }
+
+        // Manifests
+        let mut package = self.package.lock().await;
+        let tracks = package
+            .tracks()
+            .await
+            .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+        let live = self.live.load(Ordering::Relaxed);
+        let mut published = self.published.lock().await;
+        let (content_type, body) = if path == MPD_NAME {
+            // Put the latest segment at the live edge when first requested
+            let availability_start = live.then(|| {
+                let end = tracks.iter().map(ExportTrack::end).fold(0.0, f64::max);
+                *published.availability_start.get_or_insert_with(|| {
+                    Utc::now() - chrono::Duration::milliseconds((end * 1000.0) as i64)
+                })
+            });
+            ("application/dash+xml", mpd(&tracks, availability_start))
+        } else if path == MASTER_PLAYLIST_NAME {
+            (HLS_CONTENT_TYPE, master_playlist(&tracks))
+        } else {
+            let track = tracks
+                .iter()
+                .find(|t| playlist_name(&t.media_type) == path)
+                .ok_or_else(not_found)?;
+            let meta = package.store.get_meta().await.ok().flatten();
+            let playlist = published
+                .playlists
+                .entry(track.media_type.clone())
+                .or_default();
+            playlist.update(track);
+            (HLS_CONTENT_TYPE, playlist.render(meta.as_ref(), live))
+        };
+        Ok((content_type, body.into_bytes()))
+    }
+}
+
+type PlayError = (StatusCode, String);
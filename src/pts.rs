@@ -1,24 +1,80 @@
+use std::path::Path;
 use std::process::Stdio;
 
 use anyhow::Result;
+use serde::Deserialize;
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 
+use crate::error::IgLiveError;
+
 pub async fn get_pts(data: Vec<u8>) -> Result<(usize, usize)> {
+    let output = ffprobe_data("stream=start_pts,duration_ts", data).await?;
+    let (pts_start, pts_end) = output.split_once('|').ok_or(IgLiveError::CorruptSegment)?;
+    let pts_start = pts_start
+        .trim()
+        .parse()
+        .map_err(|_| IgLiveError::CorruptSegment)?;
+    let pts_end = pts_end
+        .trim()
+        .parse()
+        .map_err(|_| IgLiveError::CorruptSegment)?;
+    Ok((pts_start, pts_end))
+}
+
+/// Get the number of PTS units per second of a stream
+pub async fn get_timescale(data: Vec<u8>) -> Result<usize> {
+    let output = ffprobe_data("stream=time_base", data).await?;
+    let (num, den) = output
+        .trim()
+        .split_once('/')
+        .ok_or(IgLiveError::CorruptSegment)?;
+    match (num.parse::<usize>(), den.parse::<usize>()) {
+        (Ok(num), Ok(den)) if num > 0 => Ok(den / num),
+        _ => Err(IgLiveError::CorruptSegment.into()),
+    }
+}
+
+/// Get the duration of a media file in seconds
+pub async fn get_duration(path: impl AsRef<Path>) -> Result<f64> {
+    ffprobe_format("format=duration", path).await
+}
+
+/// Get the timestamp of the first packet of a media file in seconds
+pub async fn get_start_time(path: impl AsRef<Path>) -> Result<f64> {
+    ffprobe_format("format=start_time", path).await
+}
+
+/// Run ffprobe on a file and parse a single number from its output for `entries`
+async fn ffprobe_format(entries: &str, path: impl AsRef<Path>) -> Result<f64> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "0", "-show_entries", entries])
+        .args(["-of", "compact=p=0:nk=1"])
+        .arg(path.as_ref())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(IgLiveError::CorruptSegment.into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| IgLiveError::CorruptSegment)?)
+}
+
+/// Run ffprobe on `data` and return its output for `entries`
+async fn ffprobe_data(entries: &str, data: Vec<u8>) -> Result<String> {
+    ffprobe_data_as(entries, "compact=p=0:nk=1", data).await
+}
+
+/// Run ffprobe on `data` and return its output for `entries` in the given output format
+async fn ffprobe_data_as(entries: &str, format: &str, data: Vec<u8>) -> Result<String> {
     let mut child = Command::new("ffprobe")
-        .args([
-            "-v",
-            "0",
-            "-show_entries",
-            "stream=start_pts,duration_ts",
-            "-of",
-            "compact=p=0:nk=1",
-            "-",
-        ])
+        .args(["-v", "0", "-show_entries", entries])
+        .args(["-of", format, "-"])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
+        .spawn()?;
     let mut stdin = child.stdin.take().unwrap();
     tokio::spawn(async move {
         if let outer @ Err(e) = &stdin.write_all(&data).await {
@@ -27,13 +83,41 @@ pub async fn get_pts(data: Vec<u8>) -> Result<(usize, usize)> {
             }
         }
     });
-    let output = child.wait_with_output().await.unwrap();
+    let output = child.wait_with_output().await?;
     if !output.status.success() {
-        panic!("ffmpeg error");
+        return Err(IgLiveError::CorruptSegment.into());
     }
-    let data = String::from_utf8(output.stdout).unwrap();
-    let data = data.split_once('|').unwrap();
-    let pts_start = data.0.trim().parse().unwrap();
-    let pts_end = data.1.trim().parse().unwrap();
-    Ok((pts_start, pts_end))
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Codec parameters of the first stream in a file
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamInfo {
+    pub codec_name: String,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub r_frame_rate: Option<String>,
+    pub sample_rate: Option<String>,
+    pub channels: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct StreamInfoOutput {
+    streams: Vec<StreamInfo>,
+}
+
+/// Get the codec parameters of a stream
+pub async fn get_stream_info(data: Vec<u8>) -> Result<StreamInfo> {
+    let output = ffprobe_data_as(
+        "stream=codec_name,width,height,r_frame_rate,sample_rate,channels",
+        "json",
+        data,
+    )
+    .await?;
+    let output: StreamInfoOutput = serde_json::from_str(&output)?;
+    Ok(output
+        .streams
+        .into_iter()
+        .next()
+        .ok_or(IgLiveError::CorruptSegment)?)
 }
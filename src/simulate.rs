@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use quick_xml::escape::escape;
+use tokio::fs;
+use tokio::time::Instant;
+
+use crate::error::IgLiveError;
+use crate::mp4::init_info;
+use crate::pts::get_pts;
+use crate::store::{
+    index_by_key, sha256_hex, unix_time, FsStore, IndexEntry, MediaType, SegmentKey, SegmentSource,
+    SegmentStore, StreamMeta,
+};
+
+/// Path of the simulated manifest
+pub const MANIFEST_PATH: &str = "manifest.mpd";
+
+/// Segment time of the first generated segment
+const GENERATED_START_T: usize = 1_000_000;
+
+/// Offsets in milliseconds added to generated segment times, like IG's segment times that don't
+/// exactly follow the PTS
+const GENERATED_JITTER: [usize; 6] = [0, 1, 0, 2, 1, 0];
+
+/// Options for simulate
+#[derive(Clone, Debug)]
+pub struct SimulateConfig {
+    /// Address to listen on.
+    pub addr: SocketAddr,
+
+    /// Seconds of the stream already broadcast when the simulation starts, these are only
+    /// available as past segments.
+    pub position: f64,
+
+    /// How fast new segments are published, 1.0 is real time.
+    pub speed: f64,
+
+    /// Number of latest segments of each representation listed in the manifest.
+    pub window: usize,
+}
+
+/// Segments of one representation
+#[derive(Clone, Debug)]
+struct SimTrack {
+    media_type: MediaType,
+    representation: String,
+    codec: String,
+    width: Option<u16>,
+    height: Option<u16>,
+    bandwidth: usize,
+    /// Segment times and durations in milliseconds.
+    segments: Vec<(usize, usize)>,
+}
+
+struct Simulation {
+    store: FsStore,
+    id: String,
+    /// Segment time the stream starts at, sent as `publishFrameTime`.
+    start_frame: usize,
+    tracks: Vec<SimTrack>,
+    config: SimulateConfig,
+    started: Instant,
+}
+
+/// Serve a live stream downloaded by [download][crate::download::download] as if it was being
+/// broadcast on IG, to run downloads against it locally.
+///
+/// | Request                    | Response                                            |
+/// |----------------------------|-----------------------------------------------------|
+/// | `GET /manifest.mpd`        | Live DASH manifest of the latest segments           |
+/// | `GET /<representation>/init.mp4` | Initialization data                           |
+/// | `GET /<representation>/<t>.m4s`  | Media segment if already published, otherwise 404 |
+///
+/// Segments are published from [SimulateConfig::position] onwards, the manifest only lists the
+/// last [SimulateConfig::window] of each representation. Earlier segments can still be
+/// downloaded if their exact time is requested. Once every segment is published, the manifest is
+/// sent with the `x-fb-video-broadcast-ended` header. Runs until stopped.
+///
+/// # Arguments
+///
+/// `dir` - Directory containing downloaded video and audio segments, see [generate].
+pub async fn simulate(dir: impl AsRef<Path>, config: &SimulateConfig) -> Result<()> {
+    let simulation = Arc::new(Simulation::load(dir.as_ref(), config.clone()).await?);
+    let addr = config.addr;
+    println!(
+        "Simulating {} at http://{addr}/{MANIFEST_PATH}",
+        dir.as_ref().display()
+    );
+    let make_svc = make_service_fn(move |_| {
+        let simulation = simulation.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let simulation = simulation.clone();
+                async move { Ok::<_, Infallible>(simulation.handle(req).await) }
+            }))
+        }
+    });
+    Server::bind(&addr).serve(make_svc).await?;
+
+    Ok(())
+}
+
+/// Generate a stream of test video and audio with ffmpeg and store it in `dir` as if downloaded,
+/// for [simulate].
+///
+/// Segment times drift from the PTS by a few milliseconds and differ between video and audio,
+/// like IG's.
+///
+/// # Arguments
+///
+/// * `dir` - Directory to store the segments in.
+/// * `duration` - Length of the stream in seconds.
+pub async fn generate(dir: impl AsRef<Path>, duration: u32) -> Result<()> {
+    let dir = dir.as_ref();
+    let tmp = dir.join("generate.tmp");
+    fs::create_dir_all(&tmp).await?;
+    fs::create_dir_all(dir.join("segments")).await?;
+
+    // 2 second segments of 720x1280 video at 30 fps and AAC audio
+    let output = process::Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(["-f", "lavfi", "-i", "testsrc2=size=720x1280:rate=30"])
+        .args(["-f", "lavfi", "-i", "sine=frequency=440:sample_rate=44100"])
+        .args(["-t", &duration.to_string(), "-map", "0:v", "-map", "1:a"])
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-g", "60"])
+        .args(["-sc_threshold", "0", "-c:a", "aac", "-b:a", "96k"])
+        .args(["-f", "dash", "-seg_duration", "2", "-use_timeline", "0"])
+        .args(["-init_seg_name", "init-$RepresentationID$.m4s"])
+        .args([
+            "-media_seg_name",
+            "chunk-$RepresentationID$-$Number%05d$.m4s",
+        ])
+        .arg(tmp.join("manifest.mpd"))
+        .output();
+    let result = match output {
+        Ok(output) if output.status.success() => import(&tmp, dir).await,
+        Ok(_) => Err(IgLiveError::FfmpegFail.into()),
+        Err(e) => Err(e.into()),
+    };
+    let _ = fs::remove_dir_all(&tmp).await;
+    result
+}
+
+/// Store segments written by ffmpeg's DASH muxer in `tmp`, timed by their PTS
+async fn import(tmp: &Path, dir: &Path) -> Result<()> {
+    let store = FsStore::new(dir);
+    store
+        .put_meta(&StreamMeta {
+            id: "generated".to_owned(),
+            publish_frame_time: GENERATED_START_T,
+            broadcast_start: unix_time() as f64,
+        })
+        .await?;
+
+    for (stream, media_type) in [(0, MediaType::Video), (1, MediaType::Audio)] {
+        let representation = format!("generated-{stream}");
+        let init = fs::read(tmp.join(format!("init-{stream}.m4s"))).await?;
+        let timescale = init_info(&init).ok_or(IgLiveError::MissingInit)?.timescale as usize;
+        store.put_init(&media_type, &representation, &init).await?;
+
+        // Time every segment first, durations come from the next one
+        let mut segments = vec![];
+        for number in 1.. {
+            let path = tmp.join(format!("chunk-{stream}-{number:05}.m4s"));
+            let Ok(data) = fs::read(&path).await else {
+                break;
+            };
+            let pts = get_pts([init.clone(), data.clone()].concat()).await?;
+            let jitter = GENERATED_JITTER[number % GENERATED_JITTER.len()];
+            let t = GENERATED_START_T + pts.0 * 1000 / timescale + jitter;
+            segments.push((t, pts, data));
+        }
+        for (i, (t, pts, data)) in segments.iter().enumerate() {
+            let d = match segments.get(i + 1) {
+                Some((next, _, _)) => next - t,
+                None => (pts.1 - pts.0) * 1000 / timescale,
+            };
+            let key = SegmentKey {
+                media_type: media_type.clone(),
+                representation: representation.clone(),
+                t: *t,
+            };
+            store.put_segment(&key, data).await?;
+            store
+                .append_index(&IndexEntry {
+                    media_type: media_type.clone(),
+                    representation: representation.clone(),
+                    t: *t,
+                    d: Some(d),
+                    pts_start: pts.0,
+                    pts_end: pts.1,
+                    size: data.len(),
+                    sha256: sha256_hex(data),
+                    source: SegmentSource::Live,
+                    downloaded_at: unix_time(),
+                    url: String::new(),
+                })
+                .await?;
+        }
+        println!("Generated {} {media_type:?} segments", segments.len());
+    }
+    Ok(())
+}
+
+impl Simulation {
+    async fn load(dir: &Path, config: SimulateConfig) -> Result<Self> {
+        let store = FsStore::new(dir);
+        let meta = store.get_meta().await?;
+        let index = index_by_key(store.read_index().await?);
+        let segments = store.list_segments().await?;
+
+        let mut tracks: Vec<SimTrack> = vec![];
+        let mut sizes: HashMap<String, usize> = HashMap::new();
+        for key in segments
+            .iter()
+            .filter(|k| k.media_type != MediaType::Unknown)
+        {
+            let track = match tracks.last_mut() {
+                Some(track) if track.representation == key.representation => track,
+                _ => {
+                    let init = store
+                        .get_init(&key.media_type, &key.representation)
+                        .await?
+                        .ok_or(IgLiveError::MissingInit)?;
+                    let info = init_info(&init).ok_or(IgLiveError::MissingInit)?;
+                    tracks.push(SimTrack {
+                        media_type: key.media_type.clone(),
+                        representation: key.representation.clone(),
+                        codec: info.codec,
+                        width: info.width,
+                        height: info.height,
+                        bandwidth: 0,
+                        segments: vec![],
+                    });
+                    tracks.last_mut().unwrap()
+                }
+            };
+
+            // Segments last until the next one, the last one as long as recorded
+            if let Some(last) = track.segments.last_mut() {
+                last.1 = key.t - last.0;
+            }
+            let entry = index.get(key);
+            let d = entry
+                .and_then(|e| e.d)
+                .or(track.segments.last().map(|&(_, d)| d))
+                .unwrap_or(0);
+            track.segments.push((key.t, d));
+            let size = match entry {
+                Some(e) => e.size,
+                None => store.get_segment(key).await?.len(),
+            };
+            *sizes.entry(key.representation.clone()).or_default() += size;
+        }
+        if tracks.is_empty() {
+            bail!("No segments to simulate in {}", dir.display());
+        }
+        for track in &mut tracks {
+            let (first, last) = (track.segments[0], track.segments[track.segments.len() - 1]);
+            let duration = (last.0 + last.1).saturating_sub(first.0).max(1);
+            track.bandwidth = sizes[&track.representation] * 8 * 1000 / duration;
+        }
+
+        // Downloads stop looking for past segments at the publish frame time, so it must not be
+        // before the first segment of any track
+        let start_frame = tracks.iter().map(|t| t.segments[0].0).max().unwrap();
+        let id = format!(
+            "{}_simulated",
+            meta.map_or_else(|| "stream".to_owned(), |m| m.id)
+        );
+        Ok(Self {
+            store,
+            id,
+            start_frame,
+            tracks,
+            config,
+            started: Instant::now(),
+        })
+    }
+
+    /// Segment time up to which segments are published
+    fn live_edge(&self, elapsed: Duration) -> usize {
+        let first = self.tracks.iter().map(|t| t.segments[0].0).min().unwrap();
+        let published = self.config.position + elapsed.as_secs_f64() * self.config.speed;
+        first + (published * 1000.0) as usize
+    }
+
+    /// Segments of `track` published before `edge`
+    fn published<'a>(&self, track: &'a SimTrack, edge: usize) -> &'a [(usize, usize)] {
+        let n = track.segments.partition_point(|&(t, d)| t + d <= edge);
+        &track.segments[..n]
+    }
+
+    /// Whether every segment is published
+    fn ended(&self, edge: usize) -> bool {
+        self.tracks
+            .iter()
+            .all(|t| self.published(t, edge).len() == t.segments.len())
+    }
+
+    /// Live manifest listing the latest published segments like IG's
+    fn manifest(&self, edge: usize) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\"?>\n");
+        xml.push_str(&format!(
+            "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" type=\"dynamic\" \
+             profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" minimumUpdatePeriod=\"PT2S\" \
+             loapStreamId=\"{}\" publishFrameTime=\"{}\">\n",
+            escape(&self.id),
+            self.start_frame
+        ));
+        xml.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+        for media_type in [MediaType::Video, MediaType::Audio] {
+            let tracks: Vec<_> = self
+                .tracks
+                .iter()
+                .filter(|t| t.media_type == media_type)
+                .collect();
+            if tracks.is_empty() {
+                continue;
+            }
+            xml.push_str("    <AdaptationSet segmentAlignment=\"true\">\n");
+            for track in tracks {
+                let (mime_type, size) = match media_type {
+                    MediaType::Video => (
+                        "video/mp4",
+                        format!(
+                            " width=\"{}\" height=\"{}\"",
+                            track.width.unwrap_or(0),
+                            track.height.unwrap_or(0)
+                        ),
+                    ),
+                    _ => ("audio/mp4", String::new()),
+                };
+                let rep = escape(&track.representation);
+                xml.push_str(&format!(
+                    "      <Representation id=\"{rep}\" mimeType=\"{mime_type}\" codecs=\"{}\" \
+                     bandwidth=\"{}\"{size}>\n",
+                    escape(&track.codec),
+                    track.bandwidth
+                ));
+                xml.push_str(&format!(
+                    "        <SegmentTemplate timescale=\"1000\" \
+                     initialization=\"{rep}/init.mp4\" media=\"{rep}/$Time$.m4s\">\n"
+                ));
+                xml.push_str("          <SegmentTimeline>\n");
+                let published = self.published(track, edge);
+                let window = &published[published.len().saturating_sub(self.config.window)..];
+                for (t, d) in window {
+                    xml.push_str(&format!("            <S t=\"{t}\" d=\"{d}\"/>\n"));
+                }
+                xml.push_str("          </SegmentTimeline>\n");
+                xml.push_str("        </SegmentTemplate>\n");
+                xml.push_str("      </Representation>\n");
+            }
+            xml.push_str("    </AdaptationSet>\n");
+        }
+        xml.push_str("  </Period>\n");
+        xml.push_str("</MPD>\n");
+        xml
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        let edge = self.live_edge(self.started.elapsed());
+        let mut response = Response::builder().header("cache-control", "no-cache");
+        let result = match req.method() {
+            &Method::GET => {
+                self.get(req.uri().path().trim_start_matches('/'), edge)
+                    .await
+            }
+            _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+        };
+        let (status, content_type, body) = match result {
+            Ok((content_type, body)) => (StatusCode::OK, content_type, body),
+            Err(status) => (status, "text/plain", status.to_string().into_bytes()),
+        };
+        if req.uri().path() == format!("/{MANIFEST_PATH}") && self.ended(edge) {
+            response = response.header("x-fb-video-broadcast-ended", "1");
+        }
+        response
+            .status(status)
+            .header("content-type", content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn get(&self, path: &str, edge: usize) -> Result<(&'static str, Vec<u8>), StatusCode> {
+        if path == MANIFEST_PATH {
+            return Ok(("application/dash+xml", self.manifest(edge).into_bytes()));
+        }
+
+        let (representation, name) = path.split_once('/').ok_or(StatusCode::NOT_FOUND)?;
+        let track = self
+            .tracks
+            .iter()
+            .find(|t| t.representation == representation)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let internal_error = |_| StatusCode::INTERNAL_SERVER_ERROR;
+        if name == "init.mp4" {
+            let init = self
+                .store
+                .get_init(&track.media_type, &track.representation)
+                .await
+                .map_err(internal_error)?
+                .ok_or(StatusCode::NOT_FOUND)?;
+            return Ok(("video/mp4", init));
+        }
+
+        // Only segments already published with exactly this time exist
+        let t = name
+            .strip_suffix(".m4s")
+            .and_then(|t| t.parse::<usize>().ok())
+            .ok_or(StatusCode::NOT_FOUND)?;
+        if !self.published(track, edge).iter().any(|&(s, _)| s == t) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        let key = SegmentKey {
+            media_type: track.media_type.clone(),
+            representation: track.representation.clone(),
+            t,
+        };
+        let data = self.store.get_segment(&key).await.map_err(internal_error)?;
+        Ok(("video/mp4", data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mpd::Mpd;
+
+    #[test]
+    fn moving_window() {
+        let track = |media_type, representation: &str, start: usize| SimTrack {
+            media_type,
+            representation: representation.to_owned(),
+            codec: String::new(),
+            width: Some(720),
+            height: Some(1280),
+            bandwidth: 1000,
+            segments: (0..10).map(|i| (start + i * 2000 + i % 2, 2000)).collect(),
+        };
+        let simulation = Simulation {
+            store: FsStore::new("unused"),
+            id: "1".to_owned(),
+            start_frame: 1005,
+            tracks: vec![
+                track(MediaType::Video, "v", 1000),
+                track(MediaType::Audio, "a", 1005),
+            ],
+            config: SimulateConfig {
+                addr: ([127, 0, 0, 1], 0).into(),
+                position: 10.0,
+                speed: 1.0,
+                window: 3,
+            },
+            started: Instant::now(),
+        };
+
+        let edge = simulation.live_edge(Duration::from_secs(0));
+        let manifest: Mpd = quick_xml::de::from_str(&simulation.manifest(edge)).unwrap();
+        let (video, audio) = manifest.best_media();
+        let times = |r: &crate::mpd::Representation| {
+            r.segment_template
+                .segment_timeline
+                .segments
+                .iter()
+                .map(|s| s.t)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(video), [5000, 7001, 9000]);
+        assert_eq!(times(audio), [3006, 5005, 7006]);
+        assert_eq!(manifest.start_frame, 1005);
+        assert!(!simulation.ended(edge));
+
+        let edge = simulation.live_edge(Duration::from_secs(12));
+        assert!(simulation.ended(edge));
+    }
+}
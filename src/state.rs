@@ -1,5 +1,9 @@
 use std::collections::{HashMap, HashSet};
 
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::download::{Budget, DownloadEvent, Limit, Pipe, ProbeLog};
+use crate::merge::Recorder;
 use crate::mpd::MediaType;
 
 pub struct State {
@@ -10,10 +14,20 @@ pub struct State {
     pub deltas: HashMap<MediaType, HashMap<isize, i32>>,
 
     pub back_pts: HashMap<MediaType, usize>,
+
+    pub events: Option<UnboundedSender<DownloadEvent>>,
+
+    pub pipes: Vec<Pipe>,
+
+    pub recorder: Option<Recorder>,
+
+    pub probes: ProbeLog,
+
+    pub budget: Budget,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(events: Option<UnboundedSender<DownloadEvent>>) -> Self {
         let media_types = [MediaType::Video, MediaType::Audio];
 
         let downloaded_segs = media_types
@@ -42,6 +56,27 @@ impl State {
             downloaded_segs,
             back_pts: HashMap::new(),
             deltas,
+            events,
+            pipes: vec![],
+            recorder: None,
+            probes: ProbeLog::default(),
+            budget: Budget::default(),
+        }
+    }
+
+    pub fn emit(&self, event: DownloadEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Emit [DownloadEvent::LimitReached] the first time `limit` is reached.
+    /// Returns `false` if it was already reached before.
+    pub fn limit_reached(&mut self, limit: Limit) -> bool {
+        let first = self.budget.reach(limit);
+        if first {
+            self.emit(DownloadEvent::LimitReached { limit });
         }
+        first
     }
 }
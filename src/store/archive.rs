@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use super::{IndexEntry, MediaType, SegmentKey, SegmentStore, StreamMeta};
+use crate::error::IgLiveError;
+
+const MAGIC: &[u8; 8] = b"IGLIVE01";
+
+const KIND_INIT: u8 = 0;
+const KIND_SEGMENT: u8 = 1;
+const KIND_INDEX: u8 = 2;
+const KIND_META: u8 = 3;
+
+/// Store that appends everything to a single archive file
+///
+/// Each record is a small header followed by the data. Records written later replace earlier ones
+/// with the same key, and an incomplete record at the end of the file is discarded on open.
+#[derive(Debug)]
+pub struct ArchiveStore {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    end: u64,
+    inits: HashMap<(MediaType, String), (u64, usize)>,
+    segments: BTreeMap<SegmentKey, (u64, usize)>,
+    index: Vec<(u64, usize)>,
+    meta: Option<(u64, usize)>,
+}
+
+impl ArchiveStore {
+    /// Open an archive file, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut inner = Inner {
+            file: file.try_clone()?,
+            end: MAGIC.len() as u64,
+            inits: HashMap::new(),
+            segments: BTreeMap::new(),
+            index: vec![],
+            meta: None,
+        };
+
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+        } else {
+            let mut reader = BufReader::new(&mut file);
+            let mut magic = [0; 8];
+            reader.read_exact(&mut magic)?;
+            if &magic != MAGIC {
+                bail!("Not a segment archive");
+            }
+
+            // Index records until the end of the file or an incomplete record
+            while let Ok((kind, key)) = read_header(&mut reader) {
+                let mut len = [0; 8];
+                if reader.read_exact(&mut len).is_err() {
+                    break;
+                }
+                let len = u64::from_le_bytes(len);
+                let offset = reader.stream_position()?;
+                let end = offset.checked_add(len).ok_or(IgLiveError::CorruptArchive)?;
+                if end > reader.get_ref().metadata()?.len() {
+                    break;
+                }
+                reader.seek_relative(len as i64)?;
+
+                let entry = (offset, len as usize);
+                match kind {
+                    KIND_INIT => {
+                        inner
+                            .inits
+                            .insert((key.media_type, key.representation), entry);
+                    }
+                    KIND_INDEX => inner.index.push(entry),
+                    KIND_META => inner.meta = Some(entry),
+                    _ => {
+                        inner.segments.insert(key, entry);
+                    }
+                }
+                inner.end = end;
+            }
+        }
+        inner.file.set_len(inner.end)?;
+
+        Ok(Self {
+            inner: Mutex::new(inner),
+        })
+    }
+}
+
+impl Inner {
+    fn append(&mut self, kind: u8, key: &SegmentKey, data: &[u8]) -> Result<(u64, usize)> {
+        let rep = key.representation.as_bytes();
+        let rep_len =
+            u16::try_from(rep.len()).map_err(|_| anyhow!("Representation ID too long"))?;
+
+        let mut record = Vec::with_capacity(28 + rep.len() + data.len());
+        record.push(kind);
+        record.push(media_type_to_byte(&key.media_type));
+        record.extend_from_slice(&rep_len.to_le_bytes());
+        record.extend_from_slice(rep);
+        record.extend_from_slice(&(key.t as u64).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        let offset = self.end + record.len() as u64;
+        record.extend_from_slice(data);
+
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&record)?;
+        self.file.flush()?;
+        self.end += record.len() as u64;
+
+        Ok((offset, data.len()))
+    }
+
+    fn read(&mut self, (offset, len): (u64, usize)) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl SegmentStore for ArchiveStore {
+    async fn put_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        let key = SegmentKey {
+            media_type: media_type.clone(),
+            representation: representation.to_owned(),
+            t: 0,
+        };
+        let mut inner = self.inner.lock().await;
+        let entry = inner.append(KIND_INIT, &key, data)?;
+        inner
+            .inits
+            .insert((key.media_type, key.representation), entry);
+        Ok(())
+    }
+
+    async fn get_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut inner = self.inner.lock().await;
+        match inner
+            .inits
+            .get(&(media_type.clone(), representation.to_owned()))
+        {
+            Some(&entry) => Ok(Some(inner.read(entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put_segment(&self, key: &SegmentKey, data: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock().await;
+        let entry = inner.append(KIND_SEGMENT, key, data)?;
+        inner.segments.insert(key.clone(), entry);
+        Ok(())
+    }
+
+    async fn get_segment(&self, key: &SegmentKey) -> Result<Vec<u8>> {
+        let mut inner = self.inner.lock().await;
+        let entry = *inner
+            .segments
+            .get(key)
+            .ok_or_else(|| anyhow!("Segment not found: {key:?}"))?;
+        inner.read(entry)
+    }
+
+    async fn list_segments(&self) -> Result<Vec<SegmentKey>> {
+        Ok(self.inner.lock().await.segments.keys().cloned().collect())
+    }
+
+    async fn append_index(&self, entry: &IndexEntry) -> Result<()> {
+        let data = serde_json::to_vec(entry)?;
+        let mut inner = self.inner.lock().await;
+        let record = inner.append(KIND_INDEX, &entry.key(), &data)?;
+        inner.index.push(record);
+        Ok(())
+    }
+
+    async fn read_index(&self) -> Result<Vec<IndexEntry>> {
+        let mut inner = self.inner.lock().await;
+        let records = inner.index.clone();
+        records
+            .into_iter()
+            .map(|r| Ok(serde_json::from_slice(&inner.read(r)?)?))
+            .collect()
+    }
+
+    async fn put_meta(&self, meta: &StreamMeta) -> Result<()> {
+        let data = serde_json::to_vec(meta)?;
+        let key = SegmentKey {
+            media_type: MediaType::Unknown,
+            representation: String::new(),
+            t: 0,
+        };
+        let mut inner = self.inner.lock().await;
+        let record = inner.append(KIND_META, &key, &data)?;
+        inner.meta = Some(record);
+        Ok(())
+    }
+
+    async fn get_meta(&self) -> Result<Option<StreamMeta>> {
+        let mut inner = self.inner.lock().await;
+        match inner.meta {
+            Some(record) => Ok(Some(serde_json::from_slice(&inner.read(record)?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn read_header(reader: &mut impl Read) -> io::Result<(u8, SegmentKey)> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    let [kind, media_type, rep_len @ ..] = buf;
+    let mut rep = vec![0; u16::from_le_bytes(rep_len) as usize];
+    reader.read_exact(&mut rep)?;
+    let mut t = [0; 8];
+    reader.read_exact(&mut t)?;
+
+    let key = SegmentKey {
+        media_type: match media_type {
+            0 => MediaType::Video,
+            1 => MediaType::Audio,
+            _ => MediaType::Unknown,
+        },
+        representation: String::from_utf8_lossy(&rep).to_string(),
+        t: u64::from_le_bytes(t) as usize,
+    };
+    Ok((kind, key))
+}
+
+fn media_type_to_byte(media_type: &MediaType) -> u8 {
+    match media_type {
+        MediaType::Video => 0,
+        MediaType::Audio => 1,
+        MediaType::Unknown => 2,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::SegmentSource;
+
+    #[tokio::test]
+    async fn archive_round_trip() {
+        let path = std::env::temp_dir().join(format!("iglive-archive-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key = |t| SegmentKey {
+            media_type: MediaType::Audio,
+            representation: "rep".to_owned(),
+            t,
+        };
+        let entry = IndexEntry {
+            media_type: MediaType::Audio,
+            representation: "rep".to_owned(),
+            t: 1000,
+            d: Some(1000),
+            pts_start: 0,
+            pts_end: 44100,
+            size: 5,
+            sha256: String::new(),
+            source: SegmentSource::Live,
+            downloaded_at: 0,
+            url: String::new(),
+        };
+        let meta = StreamMeta {
+            id: "123".to_owned(),
+            publish_frame_time: 1000,
+            broadcast_start: 1.5,
+        };
+
+        {
+            let store = ArchiveStore::open(&path).unwrap();
+            store
+                .put_init(&MediaType::Audio, "rep", b"init")
+                .await
+                .unwrap();
+            store.put_segment(&key(2000), b"second").await.unwrap();
+            store.put_segment(&key(1000), b"first").await.unwrap();
+            store.put_segment(&key(2000), b"replaced").await.unwrap();
+            store.append_index(&entry).await.unwrap();
+            store.put_meta(&meta).await.unwrap();
+        }
+
+        // Simulate a crash in the middle of writing a record
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[KIND_SEGMENT, 1, 3, 0, b'r'])
+            .unwrap();
+
+        let store = ArchiveStore::open(&path).unwrap();
+        assert_eq!(
+            store.get_init(&MediaType::Audio, "rep").await.unwrap(),
+            Some(b"init".to_vec())
+        );
+        assert_eq!(store.list_segments().await.unwrap(), [key(1000), key(2000)]);
+        assert_eq!(store.get_segment(&key(2000)).await.unwrap(), b"replaced");
+        assert_eq!(store.read_index().await.unwrap(), [entry]);
+        assert_eq!(store.get_meta().await.unwrap(), Some(meta));
+        store.put_segment(&key(3000), b"third").await.unwrap();
+        assert_eq!(store.get_segment(&key(3000)).await.unwrap(), b"third");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn archive_corrupt_length() {
+        let path = std::env::temp_dir().join(format!("iglive-corrupt-{}", std::process::id()));
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[KIND_SEGMENT, 1, 1, 0, b'r']);
+        data.extend_from_slice(&1000u64.to_le_bytes());
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, data).unwrap();
+
+        let err = ArchiveStore::open(&path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IgLiveError>(),
+            Some(IgLiveError::CorruptArchive)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use super::{IndexEntry, MediaType, SegmentKey, SegmentStore, StreamMeta};
+
+/// Store that keeps files in the `segments` folder of a download directory
+///
+/// Segments listed with a name from an older version keep that name when replaced.
+/// Files are written to a temporary file first, so a file is either missing or complete.
+/// Rejected segments are moved to the `quarantine` folder with a `.txt` file containing the reason.
+/// The index is kept in `index.jsonl`, one JSON entry per line, and stream information in
+/// `stream.json`.
+///
+/// Segments are named `<representation>-<t>.m4v` or `<representation>-<t>.m4a`, initialization data
+/// is named `<representation>-init.mp4` or `<representation>-init.m4a`.
+#[derive(Debug)]
+pub struct FsStore {
+    segments_dir: PathBuf,
+    quarantine_dir: PathBuf,
+    index_path: PathBuf,
+    meta_path: PathBuf,
+
+    /// Paths of listed segments that don't use the current naming scheme
+    paths: Mutex<HashMap<SegmentKey, PathBuf>>,
+}
+
+impl FsStore {
+    /// Create a store for the download directory `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        Self {
+            segments_dir: dir.join("segments"),
+            quarantine_dir: dir.join("quarantine"),
+            index_path: dir.join("index.jsonl"),
+            meta_path: dir.join("stream.json"),
+            paths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Path of a segment's file, keeping the name it was listed with if it differs from the current
+    /// naming scheme
+    pub(crate) async fn segment_file(&self, key: &SegmentKey) -> PathBuf {
+        match self.paths.lock().await.get(key) {
+            Some(p) => p.clone(),
+            None => self.segment_path(key),
+        }
+    }
+
+    fn segment_path(&self, key: &SegmentKey) -> PathBuf {
+        let ext = match key.media_type {
+            MediaType::Video => "m4v",
+            MediaType::Audio => "m4a",
+            MediaType::Unknown => "mp4",
+        };
+        self.segments_dir
+            .join(format!("{}-{}.{}", key.representation, key.t, ext))
+    }
+
+    pub(crate) fn init_path(&self, media_type: &MediaType, representation: &str) -> PathBuf {
+        let ext = match media_type {
+            MediaType::Audio => "m4a",
+            _ => "mp4",
+        };
+        self.segments_dir
+            .join(format!("{}-init.{}", representation, ext))
+    }
+}
+
+#[async_trait]
+impl SegmentStore for FsStore {
+    async fn put_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        write_atomic(&self.init_path(media_type, representation), data).await
+    }
+
+    async fn get_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.init_path(media_type, representation)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put_segment(&self, key: &SegmentKey, data: &[u8]) -> Result<()> {
+        write_atomic(&self.segment_file(key).await, data).await
+    }
+
+    async fn get_segment(&self, key: &SegmentKey) -> Result<Vec<u8>> {
+        Ok(fs::read(self.segment_file(key).await).await?)
+    }
+
+    async fn list_segments(&self) -> Result<Vec<SegmentKey>> {
+        let mut keys = vec![];
+        let mut paths = self.paths.lock().await;
+        let mut entries = fs::read_dir(&self.segments_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            // Skip directories
+            if entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(key) = parse_segment_name(&file_name) {
+                if self.segment_path(&key) != entry.path() {
+                    paths.insert(key.clone(), entry.path());
+                }
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn quarantine(&self, key: &SegmentKey, data: &[u8], reason: &str) -> Result<()> {
+        let file_name = self.segment_path(key).file_name().unwrap().to_owned();
+        let path = self.quarantine_dir.join(file_name);
+        write_atomic(&path, data).await?;
+        write_atomic(&path.with_extension("txt"), reason.as_bytes()).await
+    }
+
+    async fn append_index(&self, entry: &IndexEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.index_path)
+            .await?;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    async fn read_index(&self) -> Result<Vec<IndexEntry>> {
+        let data = match fs::read_to_string(&self.index_path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        // Skip a partially written last line
+        Ok(data
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect())
+    }
+
+    async fn put_meta(&self, meta: &StreamMeta) -> Result<()> {
+        write_atomic(&self.meta_path, &serde_json::to_vec_pretty(meta)?).await
+    }
+
+    async fn get_meta(&self) -> Result<Option<StreamMeta>> {
+        match fs::read(&self.meta_path).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Write to a temporary file then rename it to `path`
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = path.parent().unwrap();
+    fs::create_dir_all(parent).await?;
+    let tmp = parent.join(format!(
+        ".{}.tmp",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    fs::write(&tmp, data).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Parse `<representation>-<t>.<ext>`, also accepting names taken from segment URLs
+fn parse_segment_name(name: &str) -> Option<SegmentKey> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    let media_type = match ext {
+        "m4v" => MediaType::Video,
+        "m4a" => MediaType::Audio,
+        _ => return None,
+    };
+
+    let rep = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    let t = stem[rep.len()..].parse().ok()?;
+    Some(SegmentKey {
+        media_type,
+        representation: rep.trim_end_matches(['-', '_']).to_owned(),
+        t,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn segment_names() {
+        let key = parse_segment_name("17912345_0-1690000123.m4v").unwrap();
+        assert_eq!(key.media_type, MediaType::Video);
+        assert_eq!(key.representation, "17912345_0");
+        assert_eq!(key.t, 1690000123);
+
+        let store = FsStore::new("x");
+        assert_eq!(
+            parse_segment_name(
+                &store
+                    .segment_path(&key)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+            ),
+            Some(key)
+        );
+
+        assert_eq!(parse_segment_name("17912345_0-init.m4a"), None);
+        assert_eq!(parse_segment_name("xvideo.tmp"), None);
+    }
+}
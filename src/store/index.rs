@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{MediaType, SegmentKey};
+
+/// How a segment was found
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentSource {
+    /// Listed in the manifest when the download started.
+    Current,
+    /// Listed in a manifest while following the live stream.
+    Live,
+    /// Found by the past segment search.
+    Past,
+    /// Downloaded again to replace a missing or damaged segment.
+    Repair,
+}
+
+/// Information about a downloaded segment, recorded when it is stored
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Segment media type.
+    pub media_type: MediaType,
+
+    /// ID of the representation the segment belongs to.
+    pub representation: String,
+
+    /// Segment time.
+    pub t: usize,
+
+    /// Segment duration from the manifest, if it was listed there.
+    pub d: Option<usize>,
+
+    /// First PTS of the segment.
+    pub pts_start: usize,
+
+    /// PTS after the last frame of the segment.
+    pub pts_end: usize,
+
+    /// Size of the stored fragment in bytes.
+    pub size: usize,
+
+    /// SHA-256 of the stored fragment, in hex.
+    pub sha256: String,
+
+    /// How the segment was found.
+    pub source: SegmentSource,
+
+    /// Unix time the segment was downloaded at.
+    pub downloaded_at: u64,
+
+    /// URL the segment was downloaded from.
+    pub url: String,
+}
+
+impl IndexEntry {
+    /// Key of the segment this entry describes.
+    pub fn key(&self) -> SegmentKey {
+        SegmentKey {
+            media_type: self.media_type.clone(),
+            representation: self.representation.clone(),
+            t: self.t,
+        }
+    }
+}
+
+/// Information about the live stream, recorded when the download starts
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StreamMeta {
+    /// Stream ID from the manifest.
+    pub id: String,
+
+    /// Segment time of the first frame of the broadcast, from the manifest.
+    pub publish_frame_time: usize,
+
+    /// Estimated Unix time in seconds the broadcast started at.
+    pub broadcast_start: f64,
+}
+
+/// Hex encoded SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Current Unix time in seconds
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Map index entries by segment, later entries replace earlier ones
+pub fn index_by_key(
+    entries: impl IntoIterator<Item = IndexEntry>,
+) -> HashMap<SegmentKey, IndexEntry> {
+    entries.into_iter().map(|e| (e.key(), e)).collect()
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use super::{IndexEntry, MediaType, SegmentKey, SegmentStore, StreamMeta};
+
+/// Store that keeps everything in memory
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    inits: Mutex<HashMap<(MediaType, String), Vec<u8>>>,
+    segments: Mutex<BTreeMap<SegmentKey, Vec<u8>>>,
+    quarantined: Mutex<Vec<(SegmentKey, Vec<u8>, String)>>,
+    index: Mutex<Vec<IndexEntry>>,
+    meta: Mutex<Option<StreamMeta>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get all quarantined segments with the reason they were rejected.
+    pub async fn quarantined(&self) -> Vec<(SegmentKey, Vec<u8>, String)> {
+        self.quarantined.lock().await.clone()
+    }
+}
+
+#[async_trait]
+impl SegmentStore for MemoryStore {
+    async fn put_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        self.inits.lock().await.insert(
+            (media_type.clone(), representation.to_owned()),
+            data.to_vec(),
+        );
+        Ok(())
+    }
+
+    async fn get_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .inits
+            .lock()
+            .await
+            .get(&(media_type.clone(), representation.to_owned()))
+            .cloned())
+    }
+
+    async fn put_segment(&self, key: &SegmentKey, data: &[u8]) -> Result<()> {
+        self.segments
+            .lock()
+            .await
+            .insert(key.clone(), data.to_vec());
+        Ok(())
+    }
+
+    async fn get_segment(&self, key: &SegmentKey) -> Result<Vec<u8>> {
+        self.segments
+            .lock()
+            .await
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow!("Segment not found: {key:?}"))
+    }
+
+    async fn list_segments(&self) -> Result<Vec<SegmentKey>> {
+        Ok(self.segments.lock().await.keys().cloned().collect())
+    }
+
+    async fn quarantine(&self, key: &SegmentKey, data: &[u8], reason: &str) -> Result<()> {
+        self.quarantined
+            .lock()
+            .await
+            .push((key.clone(), data.to_vec(), reason.to_owned()));
+        Ok(())
+    }
+
+    async fn append_index(&self, entry: &IndexEntry) -> Result<()> {
+        self.index.lock().await.push(entry.clone());
+        Ok(())
+    }
+
+    async fn read_index(&self) -> Result<Vec<IndexEntry>> {
+        Ok(self.index.lock().await.clone())
+    }
+
+    async fn put_meta(&self, meta: &StreamMeta) -> Result<()> {
+        *self.meta.lock().await = Some(meta.clone());
+        Ok(())
+    }
+
+    async fn get_meta(&self) -> Result<Option<StreamMeta>> {
+        Ok(self.meta.lock().await.clone())
+    }
+}
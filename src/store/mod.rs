@@ -0,0 +1,140 @@
+mod archive;
+mod fs;
+mod index;
+mod memory;
+
+use std::fmt::Debug;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::mp4::split_init;
+
+pub use self::archive::ArchiveStore;
+pub use self::fs::FsStore;
+pub(crate) use self::index::unix_time;
+pub use self::index::{index_by_key, sha256_hex, IndexEntry, SegmentSource, StreamMeta};
+pub use self::memory::MemoryStore;
+pub use crate::mpd::MediaType;
+
+/// Identifies a downloaded media segment
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct SegmentKey {
+    /// Segment media type.
+    pub media_type: MediaType,
+
+    /// ID of the representation the segment belongs to.
+    pub representation: String,
+
+    /// Segment time.
+    pub t: usize,
+}
+
+/// Storage for downloaded initialization data and media segments.
+///
+/// Implement this to keep downloads somewhere other than the local filesystem.
+#[async_trait]
+pub trait SegmentStore: Debug + Send + Sync {
+    /// Store a representation's initialization data.
+    async fn put_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+        data: &[u8],
+    ) -> Result<()>;
+
+    /// Get a representation's initialization data, if stored.
+    async fn get_init(
+        &self,
+        media_type: &MediaType,
+        representation: &str,
+    ) -> Result<Option<Vec<u8>>>;
+
+    /// Store a media segment, replacing any existing segment with the same key.
+    async fn put_segment(&self, key: &SegmentKey, data: &[u8]) -> Result<()>;
+
+    /// Get a stored media segment.
+    async fn get_segment(&self, key: &SegmentKey) -> Result<Vec<u8>>;
+
+    /// List all stored media segments, sorted by media type, representation, then time.
+    async fn list_segments(&self) -> Result<Vec<SegmentKey>>;
+
+    /// Keep a downloaded segment that was rejected, with the reason it was rejected.
+    /// Quarantined segments are never listed or merged.
+    ///
+    /// The default implementation discards the segment.
+    async fn quarantine(&self, _key: &SegmentKey, _data: &[u8], _reason: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Record information about a stored segment.
+    ///
+    /// The default implementation discards the entry.
+    async fn append_index(&self, _entry: &IndexEntry) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get all recorded index entries in the order they were recorded.
+    ///
+    /// The default implementation returns no entries.
+    async fn read_index(&self) -> Result<Vec<IndexEntry>> {
+        Ok(vec![])
+    }
+
+    /// Record information about the live stream, replacing any recorded before.
+    ///
+    /// The default implementation discards it.
+    async fn put_meta(&self, _meta: &StreamMeta) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get the recorded information about the live stream, if any.
+    ///
+    /// The default implementation returns `None`.
+    async fn get_meta(&self) -> Result<Option<StreamMeta>> {
+        Ok(None)
+    }
+}
+
+/// Get a segment as a fragment and the initialization data needed to decode it.
+///
+/// Segments stored by older versions start with their own copy of the initialization data, which
+/// is used instead of the stored one.
+pub(crate) async fn get_with_init(
+    store: &dyn SegmentStore,
+    key: &SegmentKey,
+) -> Result<(Option<Vec<u8>>, Vec<u8>)> {
+    let data = store.get_segment(key).await?;
+    if let Some((init, fragment)) = split_init(&data) {
+        return Ok((Some(init.to_vec()), fragment.to_vec()));
+    }
+    let init = store.get_init(&key.media_type, &key.representation).await?;
+    Ok((init, data))
+}
+
+/// Convert segments stored by older versions, which start with a copy of the initialization data,
+/// into a single initialization data entry per representation and raw fragments.
+/// Returns the number of converted segments.
+pub async fn migrate(store: &dyn SegmentStore) -> Result<usize> {
+    let mut count = 0;
+    for key in store.list_segments().await? {
+        let data = store.get_segment(&key).await?;
+        let Some((init, fragment)) = split_init(&data) else {
+            continue;
+        };
+
+        if store
+            .get_init(&key.media_type, &key.representation)
+            .await?
+            .is_none()
+        {
+            store
+                .put_init(&key.media_type, &key.representation, init)
+                .await?;
+        }
+        store.put_segment(&key, fragment).await?;
+        count += 1;
+    }
+    Ok(count)
+}
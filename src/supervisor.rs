@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use indicatif::MultiProgress;
+use tokio::task::JoinSet;
+
+use crate::download::{
+    download_with, CaptureLimits, DownloadConfig, DownloadContext, DownloadSegments,
+};
+use crate::fetch::{FetchConfig, Fetcher};
+use crate::merge::{merge, MergeConfig};
+
+/// Options for [Supervisor]
+#[derive(Clone, Debug)]
+pub struct SupervisorConfig {
+    /// Limits shared by all downloads.
+    pub fetch: FetchConfig,
+
+    /// Directory to place each stream's directory in.
+    /// If `None`, use the current directory.
+    pub output: Option<PathBuf>,
+
+    /// Choose whether to download live segments or past segments.
+    pub segments: DownloadSegments,
+
+    /// When to start each download, and when to stop it before the stream ends.
+    pub limits: CaptureLimits,
+
+    /// If set, merge each stream into one video file after it finishes downloading.
+    pub merge: Option<MergeConfig>,
+}
+
+/// Result of one finished stream
+#[derive(Debug)]
+pub struct JobResult {
+    /// URL of the stream's .mpd manifest.
+    pub mpd_url: String,
+
+    /// Output directory, or the error that stopped the download.
+    pub result: Result<PathBuf>,
+}
+
+/// Runs independent downloads of several live streams in one process.
+///
+/// All downloads share one HTTP client, its request and bandwidth limits, and one set of progress
+/// bars. A failing download does not affect the others.
+pub struct Supervisor {
+    ctx: DownloadContext,
+    config: SupervisorConfig,
+    urls: HashSet<String>,
+    jobs: JoinSet<JobResult>,
+}
+
+impl Supervisor {
+    /// Create a supervisor without any streams.
+    pub fn new(config: SupervisorConfig) -> Result<Self> {
+        let ctx = DownloadContext {
+            fetcher: Fetcher::new(&config.fetch)?,
+            progress: MultiProgress::new(),
+            stream_headers: true,
+        };
+        Ok(Self {
+            ctx,
+            config,
+            urls: HashSet::new(),
+            jobs: JoinSet::new(),
+        })
+    }
+
+    /// Start downloading a stream.
+    /// Returns `false` if the stream was already added.
+    pub fn add(&mut self, mpd_url: impl Into<String>) -> bool {
+        let mpd_url = mpd_url.into();
+        if !self.urls.insert(mpd_url.clone()) {
+            return false;
+        }
+
+        let ctx = self.ctx.clone();
+        let config = DownloadConfig {
+            dir: None,
+            parent_dir: self.config.output.clone(),
+            segments: self.config.segments.clone(),
+            store: None,
+            events: None,
+            pipes: vec![],
+            incremental: false,
+            probe_log: None,
+            past_range: None,
+            limits: self.config.limits.clone(),
+        };
+        let merge_config = self.config.merge.clone();
+        let url = mpd_url.clone();
+        let job = async move {
+            let dir = download_with(&ctx, url, config).await?;
+            if let Some(merge_config) = merge_config {
+                merge(&dir, &merge_config).await?;
+            }
+            Ok(dir)
+        };
+
+        self.jobs.spawn(async move {
+            // Run in its own task so a panic only fails this stream
+            let result = match tokio::spawn(job).await {
+                Ok(r) => r,
+                Err(e) => Err(anyhow!("Download task failed: {e}")),
+            };
+            JobResult { mpd_url, result }
+        });
+
+        true
+    }
+
+    /// Number of streams still downloading.
+    pub fn active(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Wait for the next stream to finish.
+    /// Returns `None` if no streams are downloading.
+    pub async fn join_next(&mut self) -> Option<JobResult> {
+        loop {
+            match self.jobs.join_next().await? {
+                Ok(r) => return Some(r),
+                Err(e) if e.is_cancelled() => continue,
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+    }
+
+    /// Print a line above the progress bars.
+    pub fn println(&self, msg: impl AsRef<str>) {
+        let _ = self.ctx.progress.println(msg);
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::merge::{merged_duration, Align, Coverage, MergeConfig};
+use crate::mp4::boxes;
+use crate::mpd::MediaType;
+use crate::pts::{get_duration, get_pts, get_timescale};
+use crate::store::{get_with_init, index_by_key, sha256_hex, FsStore, SegmentKey, SegmentStore};
+
+/// Result of [verify]
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    /// Number of segments checked.
+    pub segments: usize,
+
+    /// Duration in seconds of each track, by media type.
+    pub durations: BTreeMap<String, f64>,
+
+    /// Duration in seconds of the merged file, if it exists.
+    pub merged_duration: Option<f64>,
+
+    /// Whether the segments were compared with an index. Directories downloaded by older
+    /// versions have none.
+    pub indexed: bool,
+
+    /// Problems found, empty if everything is intact.
+    pub problems: Vec<Problem>,
+}
+
+/// Problem found by [verify]
+#[derive(Debug, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// A representation has no initialization data.
+    MissingInit {
+        /// Media type of the representation.
+        media_type: MediaType,
+        /// ID of the representation.
+        representation: String,
+    },
+    /// A segment is not a valid fragment.
+    InvalidFragment {
+        /// The segment.
+        #[serde(flatten)]
+        segment: SegmentKey,
+    },
+    /// A segment has no index entry.
+    NotIndexed {
+        /// The segment.
+        #[serde(flatten)]
+        segment: SegmentKey,
+    },
+    /// A segment's hash differs from the one recorded in the index.
+    HashMismatch {
+        /// The segment.
+        #[serde(flatten)]
+        segment: SegmentKey,
+        /// Hash recorded in the index.
+        expected: String,
+        /// Hash of the stored data.
+        actual: String,
+    },
+    /// A segment's PTS differs from the one recorded in the index.
+    PtsMismatch {
+        /// The segment.
+        #[serde(flatten)]
+        segment: SegmentKey,
+        /// Start and end PTS recorded in the index.
+        expected: (usize, usize),
+        /// Start and end PTS of the stored data.
+        actual: (usize, usize),
+    },
+    /// A track's timeline is not continuous between two segments.
+    Discontinuity {
+        /// The segment after the discontinuity.
+        #[serde(flatten)]
+        segment: SegmentKey,
+        /// End PTS of the previous segment.
+        previous_pts_end: usize,
+        /// Start PTS of this segment.
+        pts_start: usize,
+    },
+    /// The merged file could not be read.
+    MergedUnreadable {
+        /// Path of the merged file.
+        path: PathBuf,
+    },
+    /// The merged file is shorter than the downloaded segments.
+    MergedTooShort {
+        /// Path of the merged file.
+        path: PathBuf,
+        /// Shortest duration in seconds merge would write for the tracks.
+        expected: f64,
+        /// Duration of the merged file in seconds.
+        actual: f64,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seg = |s: &SegmentKey| format!("{:?} {} t={}", s.media_type, s.representation, s.t);
+        match self {
+            Self::MissingInit {
+                media_type,
+                representation,
+            } => write!(f, "{media_type:?} {representation}: missing init"),
+            Self::InvalidFragment { segment } => write!(f, "{}: invalid fragment", seg(segment)),
+            Self::NotIndexed { segment } => write!(f, "{}: not in index", seg(segment)),
+            Self::HashMismatch { segment, .. } => write!(f, "{}: hash mismatch", seg(segment)),
+            Self::PtsMismatch {
+                segment,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: PTS {actual:?} does not match index {expected:?}",
+                seg(segment)
+            ),
+            Self::Discontinuity {
+                segment,
+                previous_pts_end,
+                pts_start,
+            } => write!(
+                f,
+                "{}: starts at PTS {pts_start}, previous segment ended at {previous_pts_end}",
+                seg(segment)
+            ),
+            Self::MergedUnreadable { path } => write!(f, "{}: unreadable", path.display()),
+            Self::MergedTooShort {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: {actual:.1}s long, expected {expected:.1}s",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Check that a directory downloaded by [download][crate::download::download] is intact.
+/// `ffprobe` is required in `$PATH`.
+///
+/// Every segment must be a valid fragment that matches the hash and PTS recorded in the index,
+/// each track's timeline must be continuous, and the merged file, if it exists, must be as long as
+/// merge makes it with the `align` of `config`. Directories downloaded before the index was
+/// introduced have none, then hashes and PTS aren't compared and [VerifyReport::indexed] is
+/// `false`.
+///
+/// # Arguments
+///
+/// `dir` - Directory containing downloaded video and audio segments.
+/// `merged` - Merged file to check. If `None`, check the file [merge][crate::merge::merge] writes
+/// with `config`, unless it is split into parts.
+/// `config` - Options the directory was merged with.
+pub async fn verify(
+    dir: impl AsRef<Path>,
+    merged: Option<PathBuf>,
+    config: &MergeConfig,
+) -> Result<VerifyReport> {
+    let merged = merged.or_else(|| {
+        config
+            .split
+            .is_none()
+            .then(|| config.output_path(dir.as_ref()))
+    });
+    verify_from(&FsStore::new(dir.as_ref()), merged.as_deref(), config).await
+}
+
+/// Check that the segments in `store` are intact.
+/// See [verify].
+///
+/// # Arguments
+///
+/// `store` - Store containing downloaded video and audio segments.
+/// `merged` - Merged file to check, skipped if it doesn't exist.
+/// `config` - Options the directory was merged with.
+pub async fn verify_from(
+    store: &dyn SegmentStore,
+    merged: Option<&Path>,
+    config: &MergeConfig,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let index = index_by_key(store.read_index().await?);
+    report.indexed = !index.is_empty();
+    let mut missing_init = HashSet::new();
+    let mut previous: Option<(SegmentKey, usize)> = None;
+    let mut timescales = BTreeMap::new();
+    let mut durations: BTreeMap<MediaType, usize> = BTreeMap::new();
+    let mut spans: BTreeMap<MediaType, (usize, usize)> = BTreeMap::new();
+
+    for key in store.list_segments().await? {
+        report.segments += 1;
+        let (init, fragment) = get_with_init(store, &key).await?;
+        let Some(init) = init else {
+            if missing_init.insert((key.media_type.clone(), key.representation.clone())) {
+                report.problems.push(Problem::MissingInit {
+                    media_type: key.media_type.clone(),
+                    representation: key.representation.clone(),
+                });
+            }
+            continue;
+        };
+
+        // Check fragment structure and probe it
+        let is_fragment = boxes(&fragment).is_some_and(|b| {
+            b.iter().any(|b| &b.kind == b"moof") && b.iter().any(|b| &b.kind == b"mdat")
+        });
+        let pts = if is_fragment {
+            get_pts([init.clone(), fragment.clone()].concat())
+                .await
+                .ok()
+        } else {
+            None
+        };
+        let Some(pts) = pts else {
+            report
+                .problems
+                .push(Problem::InvalidFragment { segment: key });
+            continue;
+        };
+        if !timescales.contains_key(&key.media_type) {
+            if let Ok(timescale) = get_timescale(init).await {
+                timescales.insert(key.media_type.clone(), timescale);
+            }
+        }
+        *durations.entry(key.media_type.clone()).or_default() += pts.1.saturating_sub(pts.0);
+        let span = spans.entry(key.media_type.clone()).or_insert(pts);
+        *span = (span.0.min(pts.0), span.1.max(pts.1));
+
+        // Compare with index, if there is one
+        match index.get(&key) {
+            Some(entry) => {
+                let hash = sha256_hex(&fragment);
+                if hash != entry.sha256 {
+                    report.problems.push(Problem::HashMismatch {
+                        segment: key.clone(),
+                        expected: entry.sha256.clone(),
+                        actual: hash,
+                    });
+                }
+                if (entry.pts_start, entry.pts_end) != pts {
+                    report.problems.push(Problem::PtsMismatch {
+                        segment: key.clone(),
+                        expected: (entry.pts_start, entry.pts_end),
+                        actual: pts,
+                    });
+                }
+            }
+            None if report.indexed => report.problems.push(Problem::NotIndexed {
+                segment: key.clone(),
+            }),
+            None => {}
+        }
+
+        // Check continuity within the track
+        if let Some((prev_key, prev_end)) = &previous {
+            let same_track = prev_key.media_type == key.media_type
+                && prev_key.representation == key.representation;
+            if same_track && prev_end.abs_diff(pts.0) > 1 {
+                report.problems.push(Problem::Discontinuity {
+                    segment: key.clone(),
+                    previous_pts_end: *prev_end,
+                    pts_start: pts.0,
+                });
+            }
+        }
+        previous = Some((key, pts.1));
+    }
+
+    // Convert durations to seconds
+    let coverages: BTreeMap<_, _> = durations
+        .into_iter()
+        .filter_map(|(media_type, d)| {
+            let timescale = *timescales.get(&media_type)? as f64;
+            let (start, end) = spans[&media_type];
+            let coverage = Coverage {
+                start: start as f64 / timescale,
+                end: end as f64 / timescale,
+                covered: d as f64 / timescale,
+            };
+            Some((media_type, coverage))
+        })
+        .collect();
+    report.durations = coverages
+        .iter()
+        .filter_map(|(media_type, c)| {
+            let name = serde_json::to_value(media_type).ok()?.as_str()?.to_owned();
+            Some((name, c.covered))
+        })
+        .collect();
+
+    // Check merged file
+    let expected = expected_duration(&coverages, config.align);
+    if let (Some(path), Some(expected)) = (merged.filter(|p| p.exists()), expected) {
+        match get_duration(path).await {
+            Ok(actual) => {
+                report.merged_duration = Some(actual);
+                // Allow up to one second of difference between tracks and container
+                if actual + 1.0 < expected {
+                    report.problems.push(Problem::MergedTooShort {
+                        path: path.to_owned(),
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            Err(_) => report.problems.push(Problem::MergedUnreadable {
+                path: path.to_owned(),
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Shortest duration in seconds of the file merge writes for tracks with the given coverage.
+/// Returns `None` if there is nothing to merge.
+fn expected_duration(coverages: &BTreeMap<MediaType, Coverage>, align: Align) -> Option<f64> {
+    let video = coverages.get(&MediaType::Video);
+    match coverages.get(&MediaType::Audio) {
+        Some(audio) => merged_duration(video, audio, align).ok(),
+        None => merged_duration(None, video?, align).ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[tokio::test]
+    async fn structural_problems() {
+        let store = MemoryStore::new();
+        let key = |media_type, t| SegmentKey {
+            media_type,
+            representation: "rep".to_owned(),
+            t,
+        };
+        store
+            .put_init(&MediaType::Video, "rep", b"init")
+            .await
+            .unwrap();
+        store
+            .put_segment(&key(MediaType::Video, 0), b"garbage")
+            .await
+            .unwrap();
+        store
+            .put_segment(&key(MediaType::Audio, 0), b"garbage")
+            .await
+            .unwrap();
+
+        let report = verify_from(&store, None, &MergeConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(report.segments, 2);
+        assert!(matches!(
+            &report.problems[..],
+            [
+                Problem::InvalidFragment { .. },
+                Problem::MissingInit {
+                    media_type: MediaType::Audio,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn expected_with_unequal_tracks() {
+        // Audio was searched back 2s less and has 1s missing
+        let coverages = BTreeMap::from([
+            (
+                MediaType::Video,
+                Coverage {
+                    start: 0.0,
+                    end: 20.0,
+                    covered: 20.0,
+                },
+            ),
+            (
+                MediaType::Audio,
+                Coverage {
+                    start: 2.0,
+                    end: 21.0,
+                    covered: 18.0,
+                },
+            ),
+        ]);
+        assert_eq!(expected_duration(&coverages, Align::Trim), Some(17.0));
+        assert_eq!(expected_duration(&coverages, Align::Pad), Some(20.0));
+
+        let video_only = BTreeMap::from([(MediaType::Video, coverages[&MediaType::Video].clone())]);
+        assert_eq!(expected_duration(&video_only, Align::Trim), Some(20.0));
+        assert_eq!(expected_duration(&BTreeMap::new(), Align::Trim), None);
+    }
+}